@@ -0,0 +1,267 @@
+use crate::door::{self, Door};
+use crate::level::{self, Level, Room, RoomCoord};
+use crate::math::{Vec2, vec2, Rect};
+
+/// A rectangle that is either walkable or in the way, before making room for
+/// the radius of whatever is moving around
+#[derive(Clone, Debug)]
+struct Area {
+    rect: Rect,
+    /// How far each side moves inwards per unit of radius. Negative for
+    /// areas that grow instead
+    margin: Vec2,
+    /// The room and door offset of the doorway that the area is, which a
+    /// closed door blocks
+    doorway: Option<(RoomCoord, (i8, i8))>,
+}
+
+impl Area {
+    fn fitted(&self, radius: f32) -> Rect {
+        self.rect.shrunk(self.margin * radius)
+    }
+}
+
+/// Where in a level things can be, worked out once per level. See
+/// `Level::collision_areas`
+#[derive(Clone, Debug, Default)]
+pub struct CollisionAreas {
+    walkable: Vec<Area>,
+    obstacles: Vec<Area>,
+}
+
+impl CollisionAreas {
+    /// Doorways are stretched into the neighbouring rooms so that the areas
+    /// overlap and it is possible to walk between them. Corridors only
+    /// consist of the strips leading to their doors.
+    pub fn new(level: &Level) -> CollisionAreas {
+        let mut walkable = vec![];
+        let room = |rect| Area { rect, margin: vec2(1., 1.), doorway: None };
+
+        for (col, rooms) in level.rooms.iter().enumerate() {
+            for (row, room_kind) in rooms.iter().enumerate() {
+                let doorways = match room_kind {
+                    Room::FullRoom(doorways) => {
+                        walkable.push(room(level::room_bounds(col, row)));
+                        doorways
+                    }
+                    Room::Corridor(doorways) => {
+                        let strips = level::corridor_areas((col, row), doorways);
+                        walkable.extend(strips.into_iter().map(room));
+                        doorways
+                    }
+                    Room::Empty => continue,
+                };
+
+                for &door in doorways {
+                    let (a, b) = level::doorway_bounds((col, row), door);
+                    let margin = match door {
+                        // Doors leading up or down are walked through along y
+                        (0, _) => vec2(1., -1.),
+                        _ => vec2(-1., 1.),
+                    };
+                    walkable.push(Area {
+                        rect: Rect::from_corners(a, b),
+                        margin,
+                        doorway: Some(((col, row), door)),
+                    });
+                }
+            }
+        }
+
+        let obstacles = level.props
+            .iter()
+            .filter(|prop| prop.kind.is_solid())
+            .map(|prop| Area { rect: prop.bounds(), margin: vec2(-1., -1.), doorway: None })
+            .collect();
+
+        CollisionAreas { walkable, obstacles }
+    }
+
+    /// Areas that something with the given radius can stand in without
+    /// touching a wall. Doorways with a closed door are left out.
+    fn walkable<'a>(&'a self, doors: &'a [Door], radius: f32) -> impl Iterator<Item = Rect> + 'a {
+        self.walkable
+            .iter()
+            .filter(move |area| match area.doorway {
+                Some((room, door)) => !door::is_blocked(doors, room, door),
+                None => true,
+            })
+            .map(move |area| area.fitted(radius))
+    }
+
+    /// The areas taken up by solid props, grown by `radius`
+    fn obstacles(&self, radius: f32) -> impl Iterator<Item = Rect> + '_ {
+        self.obstacles.iter().map(move |area| area.fitted(radius))
+    }
+
+    fn is_walkable(&self, doors: &[Door], position: Vec2, radius: f32) -> bool {
+        self.walkable(doors, radius).any(|area| area.contains(position))
+            && !self.obstacles(radius).any(|obstacle| obstacle.contains(position))
+    }
+
+    /// How far `position` is from being walkable: the distance to the
+    /// closest walkable area plus how deep it is inside obstacles
+    fn overlap(&self, doors: &[Door], position: Vec2, radius: f32) -> f32 {
+        let outside = self.walkable(doors, radius)
+            .map(|area| area.distance_to(position))
+            .fold(f32::INFINITY, f32::min);
+        let inside: f32 = self.obstacles(radius)
+            .map(|obstacle| obstacle.depth(position))
+            .sum();
+        outside + inside
+    }
+}
+
+pub fn is_walkable(level: &Level, doors: &[Door], position: Vec2, radius: f32) -> bool {
+    level.collision_areas().is_walkable(doors, position, radius)
+}
+
+/// Moves from `position` by `movement`, sliding along any walls in the way.
+/// Anything that is already stuck in a wall, like an agent that a door
+/// closed on, can only move in ways that get it closer to walkable ground.
+pub fn move_with_collision(
+    level: &Level,
+    doors: &[Door],
+    position: Vec2,
+    movement: Vec2,
+    radius: f32,
+) -> Vec2 {
+    let areas = level.collision_areas();
+    let walkable = |p: Vec2| areas.is_walkable(doors, p, radius);
+    let overlap = |p: Vec2| areas.overlap(doors, p, radius);
+
+    let mut result = position;
+    // Each axis is handled separately to let the player slide along walls
+    for step in &[vec2(movement.x, 0.), vec2(0., movement.y)] {
+        let next = result + *step;
+        if walkable(next) || (!walkable(result) && overlap(next) < overlap(result)) {
+            result = next;
+        }
+    }
+    result
+}
+
+/// How much of the line from `from` to `to` can be travelled before hitting
+/// a wall, as a fraction of its length
+fn clear_fraction(level: &Level, doors: &[Door], from: Vec2, to: Vec2) -> f32 {
+    let mut inside: Vec<_> = level.collision_areas()
+        .walkable(doors, 0.)
+        .filter_map(|area| area.clip_segment(from, to))
+        .collect();
+    inside.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // The pieces of the line that are inside some area have to follow each
+    // other, small gaps are allowed where the areas only touch
+    let mut covered = 0.;
+    for (enter, exit) in inside {
+        if enter > covered + 1e-4 {
+            break;
+        }
+        covered = exit.max(covered);
+    }
+    covered
+}
+
+/// Whether the straight line between two points stays inside rooms and
+/// doorways without passing through any walls or closed doors
+pub fn line_of_sight(level: &Level, doors: &[Door], from: Vec2, to: Vec2) -> bool {
+    clear_fraction(level, doors, from, to) >= 1. - 1e-4
+}
+
+/// How far a ray from `from` in the normalized `direction` gets before it
+/// hits a wall or a closed door, up to `max_distance`
+pub fn raycast(
+    level: &Level,
+    doors: &[Door],
+    from: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+) -> f32 {
+    let to = from + direction * max_distance;
+    clear_fraction(level, doors, from, to) * max_distance
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::constants::{PLAYER_RADIUS, ROOM_LENGTH};
+    use crate::door::DoorState;
+
+    /// Three rooms on top of each other, where only the upper two have a
+    /// doorway between them
+    fn stacked_rooms() -> Level {
+        let mut rooms: [Vec<Room>; 8] = Default::default();
+        for (col, column) in rooms.iter_mut().enumerate() {
+            *column = vec![Room::Empty; level::rooms_in_col(col)];
+        }
+        rooms[3][0] = Room::FullRoom(vec![(0, 1)]);
+        rooms[3][1] = Room::FullRoom(vec![(0, -1)]);
+        rooms[3][2] = Room::FullRoom(vec![]);
+        Level {
+            rooms,
+            bases: vec![],
+            spawns: HashMap::new(),
+            props: vec![],
+            collision_areas: Default::default(),
+        }
+    }
+
+    #[test]
+    fn walls_block() {
+        let level = stacked_rooms();
+        let (top, bottom) = (level::room_center(3, 1), level::room_center(3, 2));
+        let wall = (top + bottom) / 2.;
+
+        assert!(!is_walkable(&level, &[], wall, PLAYER_RADIUS));
+        assert!(!line_of_sight(&level, &[], top, bottom));
+
+        let distance = raycast(&level, &[], top, vec2(0., 1.), 10.);
+        assert!((distance - ROOM_LENGTH / 2.).abs() < 1e-3);
+
+        let mut position = top;
+        for _ in 0..100 {
+            position = move_with_collision(&level, &[], position, vec2(0., 0.1), PLAYER_RADIUS);
+        }
+        assert!(level::room_bounds(3, 1).contains(position));
+
+        // Something stuck in the wall can only leave the way it came
+        let stuck = wall - vec2(0., 0.1);
+        let deeper = move_with_collision(&level, &[], stuck, vec2(0., 0.1), PLAYER_RADIUS);
+        assert_eq!(deeper, stuck);
+        let back = move_with_collision(&level, &[], stuck, vec2(0., -0.1), PLAYER_RADIUS);
+        assert!(back.y < stuck.y);
+    }
+
+    #[test]
+    fn doorways_are_passable() {
+        let level = stacked_rooms();
+        let (top, bottom) = (level::room_center(3, 0), level::room_center(3, 1));
+        let doorway = (top + bottom) / 2.;
+
+        assert!(is_walkable(&level, &[], doorway, PLAYER_RADIUS));
+        assert!(line_of_sight(&level, &[], top, bottom));
+
+        let mut position = top;
+        for _ in 0..100 {
+            position = move_with_collision(&level, &[], position, vec2(0., 0.1), PLAYER_RADIUS);
+        }
+        assert!(level::room_bounds(3, 1).contains(position));
+    }
+
+    #[test]
+    fn closed_doors_block() {
+        let level = stacked_rooms();
+        let doors = [Door {
+            room: (3, 0),
+            direction: (0, 1),
+            state: DoorState::Closed,
+            owner: None,
+        }];
+        let (top, bottom) = (level::room_center(3, 0), level::room_center(3, 1));
+
+        assert!(!is_walkable(&level, &doors, (top + bottom) / 2., PLAYER_RADIUS));
+        assert!(!line_of_sight(&level, &doors, top, bottom));
+    }
+}
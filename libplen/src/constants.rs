@@ -1,5 +1,12 @@
-pub const TEAM_RED_ID: u64 = 0;
-pub const TEAM_BLUE_ID: u64 = 1;
+/// Names and colours of the teams that a server can have, in the order that
+/// they are added
+pub const TEAMS: [(&str, (u8, u8, u8)); 4] = [
+    ("RED", (255, 0, 0)),
+    ("BLUE", (0, 0, 255)),
+    ("GREEN", (0, 170, 0)),
+    ("YELLOW", (220, 190, 0)),
+];
+pub const DEFAULT_TEAM_COUNT: usize = 2;
 
 // currently hardcoded to the background image size
 pub const WORLD_SIZE: f32 = 3000.;
@@ -12,6 +19,25 @@ pub const NAME_POS: (f32, f32) = (50., 100.);
 pub const STATUS_TEXT_POS: (f32, f32) = (50., 200.);
 
 pub const PLAYER_SPEED: f32 = 1.5;
+pub const PLAYER_RADIUS: f32 = 0.25;
+pub const PLAYER_MAX_HEALTH: f32 = 100.;
+pub const RESPAWN_TIME: f32 = 5.;
+pub const TDM_RESPAWN_TIME: f32 = 3.;
+
+pub const WEAPON_RANGE: f32 = 30.;
+pub const GUN_DAMAGE: f32 = 20.;
+pub const GUN_COOLDOWN: f32 = 0.3;
+pub const LASER_DAMAGE: f32 = 75.;
+pub const LASER_CHARGE_TIME: f32 = 1.;
+pub const LASER_COOLDOWN: f32 = 2.;
+pub const SHOT_DURATION: f32 = 0.2;
+/// The oldest shots and hits are dropped beyond these so that a snapshot
+/// always fits in a message
+pub const MAX_SHOTS: usize = 256;
+pub const MAX_HITS: usize = 256;
+
+/// Sounds further away than this are not played at all
+pub const HEARING_DISTANCE: f32 = 30.;
 
 pub const ROOM_WIDTH: f32 = 3.;
 pub const ROOM_LENGTH: f32 = 5.;
@@ -19,18 +45,51 @@ pub const CEILING_HEIGHT: f32 = 2.5;
 pub const DOOR_HEIGHT: f32 = 2.1;
 pub const DOORWAY_LENGTH: f32 = 0.75;
 pub const DOOR_WIDTH: f32 = 1.2;
+// Corridors end in the doorways, so they have to be exactly as wide
+pub const CORRIDOR_WIDTH: f32 = DOOR_WIDTH;
+
+pub const COUNTDOWN_DURATION: f32 = 3.;
+pub const RESULTS_DURATION: f32 = 8.;
+pub const DEFAULT_TIME_LIMIT: f32 = 300.;
+pub const DEFAULT_SCORE_LIMIT: u32 = 3;
+pub const DEFAULT_KILL_LIMIT: u32 = 20;
+pub const DEFAULT_HACK_LIMIT: u32 = 2;
+
+pub const FLAG_PICKUP_RADIUS: f32 = 0.5;
+pub const FLAG_CAPTURE_RADIUS: f32 = 1.;
+
+pub const KILLFEED_DURATION: f32 = 6.;
+pub const KILLFEED_LENGTH: usize = 5;
+
+pub const PROP_PICKUP_RADIUS: f32 = 0.5;
+pub const BARREL_HEALTH: f32 = 30.;
+pub const EXPLOSION_RADIUS: f32 = 1.5;
+pub const EXPLOSION_DAMAGE: f32 = 60.;
+/// How close to a server rack an agent has to be to hack or repair it
+pub const HACK_RADIUS: f32 = 1.;
+/// Seconds that it takes a single agent to hack a rack
+pub const HACK_TIME: f32 = 10.;
+/// How much faster agents carrying a GPU hack
+pub const GPU_HACK_SPEEDUP: f32 = 2.;
+
+pub const MARKER_DURATION: f32 = 20.;
+pub const MAX_MARKERS: usize = 8;
 
 pub const SCREEN_PADDING: f32 = 0.5;
 
 pub const MENU_BACKGROUND_COLOR: (u8, u8, u8) = (30, 30, 30);
-pub const MENU_CENTER_LINE_COLOR: (u8, u8, u8) = (0, 0, 0);
-pub const MENU_CENTER_LINE_WIDTH: u32 = 10;
-pub const MENU_RED_BACKGROUND_COLOR: (u8, u8, u8) = (30, 0, 0);
-pub const MENU_BLUE_BACKGROUND_COLOR: (u8, u8, u8) = (0, 0, 30);
+pub const MENU_DIVIDER_COLOR: (u8, u8, u8) = (0, 0, 0);
+pub const MENU_DIVIDER_WIDTH: u32 = 10;
+/// How bright the team colours are behind and on the buttons of the team
+pub const MENU_BACKGROUND_BRIGHTNESS: f32 = 0.12;
+pub const MENU_BUTTON_BRIGHTNESS: f32 = 0.27;
 
+pub const MENU_TEAM_NAME_Y: f32 = 1./4.;
 pub const MENU_BUTTON_JOIN_DISPATCHER_Y: f32 = 1./3.;
 pub const MENU_BUTTON_JOIN_AGENT_Y: f32 = 1./2.;
+pub const MENU_BUTTON_AUTO_ASSIGN_POS: (f32, f32) = (0.45, 0.58);
+pub const MENU_BUTTON_READY_POS: (f32, f32) = (0.45, 2./3.);
 pub const MENU_BUTTON_HEIGHT: u32 = 30;
 pub const MENU_BUTTON_WIDTH: u32 = 100;
-pub const MENU_RED_BUTTON_COLOR: (u8, u8, u8) = (70, 10, 10);
-pub const MENU_BLUE_BUTTON_COLOR: (u8, u8, u8) = (10, 10, 70);
+pub const MENU_READY_BUTTON_COLOR: (u8, u8, u8) = (10, 70, 10);
+pub const MENU_AUTO_ASSIGN_BUTTON_COLOR: (u8, u8, u8) = (60, 60, 60);
@@ -0,0 +1,112 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::level::{self, Level, RoomCoord};
+use crate::math::Rect;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DoorState {
+    Open,
+    Closed,
+    /// Closed, and only the dispatcher of the owning team can open it
+    Locked,
+}
+
+impl DoorState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DoorState::Open => "open",
+            DoorState::Closed => "closed",
+            DoorState::Locked => "locked",
+        }
+    }
+}
+
+/// A door in the doorway between two rooms, which the dispatchers control
+/// remotely
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Door {
+    /// The first of the two rooms, in the order that `RoomCoord`s compare
+    pub room: RoomCoord,
+    /// The door offset from `room` to the other room
+    pub direction: (i8, i8),
+    pub state: DoorState,
+    /// The team that locked the door last. Doors into a team base start out
+    /// belonging to that team
+    pub owner: Option<u64>,
+}
+
+impl Door {
+    pub fn target(&self) -> RoomCoord {
+        level::door_target(self.room, self.direction).expect("Doors lead to other rooms")
+    }
+
+    /// Whether this is the door between the two rooms, in either order
+    pub fn connects(&self, a: RoomCoord, b: RoomCoord) -> bool {
+        let target = self.target();
+        (self.room, target) == (a, b) || (self.room, target) == (b, a)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state == DoorState::Open
+    }
+
+    /// The whole doorway, made up of the halves belonging to each room
+    pub fn bounds(&self) -> Rect {
+        let (dx, dy) = self.direction;
+        let (a, b) = level::doorway_bounds(self.room, self.direction);
+        let (c, d) = level::doorway_bounds(self.target(), (-dx, -dy));
+        let (first, second) = (Rect::from_corners(a, b), Rect::from_corners(c, d));
+        Rect {
+            min: first.min.min_by_component(second.min),
+            max: first.max.max_by_component(second.max),
+        }
+    }
+
+    /// Whether the dispatcher of `team_id` is allowed to put the door in
+    /// `state`. Anyone can open and close unlocked doors, but locking and
+    /// unlocking is up to the owner, if there is one
+    pub fn can_change(&self, team_id: u64, state: DoorState) -> bool {
+        let owned_by_us = self.owner.map(|owner| owner == team_id).unwrap_or(true);
+        if self.state == DoorState::Locked || state == DoorState::Locked {
+            owned_by_us
+        } else {
+            true
+        }
+    }
+}
+
+/// An open door for every doorway of the level. `bases` are the ids and base
+/// rooms of the teams, which get to own the doors into their base
+pub fn level_doors(level: &Level, bases: &[(u64, RoomCoord)]) -> Vec<Door> {
+    let mut doors = vec![];
+    for (col, rooms) in level.rooms.iter().enumerate() {
+        for (row, room) in rooms.iter().enumerate() {
+            for &direction in room.doors() {
+                let target = match level::door_target((col, row), direction) {
+                    Some(target) if (col, row) < target => target,
+                    _ => continue,
+                };
+                let owner = bases.iter()
+                    .find(|&&(_, base)| base == (col, row) || base == target)
+                    .map(|&(team_id, _)| team_id);
+                doors.push(Door {
+                    room: (col, row),
+                    direction,
+                    state: DoorState::Open,
+                    owner,
+                });
+            }
+        }
+    }
+    doors
+}
+
+/// Whether the doorway leading out of `room` in `direction` is shut. Doors
+/// that are not in the list are open
+pub fn is_blocked(doors: &[Door], room: RoomCoord, direction: (i8, i8)) -> bool {
+    let target = match level::door_target(room, direction) {
+        Some(target) => target,
+        None => return false,
+    };
+    doors.iter().any(|door| door.connects(room, target) && !door.is_open())
+}
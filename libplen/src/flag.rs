@@ -0,0 +1,45 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::math::Vec2;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Flag {
+    pub team_id: u64,
+    pub home: Vec2,
+    pub position: Vec2,
+    pub carrier: Option<u64>,
+}
+
+impl Flag {
+    pub fn new(team_id: u64, home: Vec2) -> Flag {
+        Flag {
+            team_id,
+            home,
+            position: home,
+            carrier: None,
+        }
+    }
+
+    pub fn is_home(&self) -> bool {
+        self.carrier.is_none() && self.position == self.home
+    }
+
+    pub fn return_home(&mut self) {
+        self.carrier = None;
+        self.position = self.home;
+    }
+
+    /// Leaves the flag lying where the carrier last was
+    pub fn drop(&mut self) {
+        self.carrier = None;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Capture {
+    /// The team that scored
+    pub team_id: u64,
+    pub player_id: u64,
+    /// The team whose flag was captured
+    pub flag_team_id: u64,
+}
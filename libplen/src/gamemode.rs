@@ -0,0 +1,264 @@
+use enum_dispatch::enum_dispatch;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::constants::{
+    DEFAULT_HACK_LIMIT, DEFAULT_KILL_LIMIT, DEFAULT_SCORE_LIMIT, GPU_HACK_SPEEDUP, HACK_RADIUS,
+    HACK_TIME, RESPAWN_TIME, TDM_RESPAWN_TIME, TEAMS,
+};
+use crate::gamestate::GameState;
+use crate::level::Level;
+use crate::messages::ClientInput;
+use crate::player::PlayerType;
+use crate::prop::{Prop, PropKind};
+use crate::weapon::Hit;
+
+/// The rules of a match: how teams score, what it takes to win and what
+/// happens while the match is in progress
+#[enum_dispatch]
+pub trait GameMode {
+    fn name(&self) -> &'static str;
+
+    /// What a team has to do to win, as in "the first team that ..."
+    fn goal(&self) -> String;
+
+    /// The score that a team needs to win the match
+    fn score_limit(&self, state: &GameState, team_id: u64) -> u32;
+
+    /// Whether matches between `team_count` teams can be won on `level`
+    fn check_level(&self, _level: &Level, _team_count: usize) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether the flags are in play
+    fn has_flags(&self) -> bool {
+        false
+    }
+
+    /// How long killed agents have to wait before respawning
+    fn respawn_time(&self) -> f32 {
+        RESPAWN_TIME
+    }
+
+    /// Rules that run once per tick while the match is in progress, after
+    /// every agent has been updated
+    fn update(&self, _state: &mut GameState, _delta: f32, _level: &Level) {}
+
+    /// Rules that run once per tick for every agent, with their input
+    fn update_agent(
+        &self,
+        _state: &mut GameState,
+        _player_id: u64,
+        _input: &ClientInput,
+        _delta: f32,
+    ) {}
+
+    /// Called whenever a hit kills an agent
+    fn on_kill(&self, _state: &mut GameState, _hit: Hit) {}
+}
+
+/// The mode that the server was configured to play
+#[enum_dispatch(GameMode)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    CaptureTheFlag,
+    TeamDeathmatch,
+    Hacking,
+}
+
+impl Mode {
+    /// The mode called `name`. Without a `score_limit`, the default limit of
+    /// the mode is used
+    pub fn from_config(name: &str, score_limit: Option<u32>) -> Result<Mode, String> {
+        let mode = match name.to_lowercase().as_str() {
+            "ctf" | "capture_the_flag" => CaptureTheFlag {
+                score_limit: score_limit.unwrap_or(DEFAULT_SCORE_LIMIT),
+            }.into(),
+            "tdm" | "team_deathmatch" => TeamDeathmatch {
+                kill_limit: score_limit.unwrap_or(DEFAULT_KILL_LIMIT),
+            }.into(),
+            "objective" | "hacking" => Hacking {
+                hack_limit: score_limit.unwrap_or(DEFAULT_HACK_LIMIT),
+            }.into(),
+            _ => return Err(format!("unknown game mode {:?}", name)),
+        };
+        Ok(mode)
+    }
+}
+
+/// Teams score by bringing the flags of the other teams home to their base
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CaptureTheFlag {
+    pub score_limit: u32,
+}
+
+impl GameMode for CaptureTheFlag {
+    fn name(&self) -> &'static str {
+        "Capture the flag"
+    }
+
+    fn goal(&self) -> String {
+        format!("reaches {} points", self.score_limit)
+    }
+
+    fn score_limit(&self, _state: &GameState, _team_id: u64) -> u32 {
+        self.score_limit
+    }
+
+    fn has_flags(&self) -> bool {
+        true
+    }
+
+    fn update(&self, state: &mut GameState, _delta: f32, level: &Level) {
+        if state.update_flags() {
+            state.reset_round(level);
+        }
+    }
+}
+
+/// Teams score by killing the agents of the other teams
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TeamDeathmatch {
+    pub kill_limit: u32,
+}
+
+impl GameMode for TeamDeathmatch {
+    fn name(&self) -> &'static str {
+        "Team deathmatch"
+    }
+
+    fn goal(&self) -> String {
+        format!("reaches {} kills", self.kill_limit)
+    }
+
+    fn score_limit(&self, _state: &GameState, _team_id: u64) -> u32 {
+        self.kill_limit
+    }
+
+    fn respawn_time(&self) -> f32 {
+        TDM_RESPAWN_TIME
+    }
+
+    fn on_kill(&self, state: &mut GameState, hit: Hit) {
+        // Killing yourself or your teammates is not worth anything
+        let shooter_team = state.team_of_player(hit.shooter);
+        if shooter_team.is_none() || shooter_team == state.team_of_player(hit.target) {
+            return;
+        }
+        if let Some(team) = shooter_team.and_then(|team_id| state.teams.get_mut(&team_id)) {
+            team.score += 1;
+        }
+    }
+}
+
+/// The objective mode. Teams score by hacking the server racks of the other
+/// teams, which takes a while of standing next to them. Carrying a GPU
+/// speeds up hacking
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Hacking {
+    pub hack_limit: u32,
+}
+
+impl GameMode for Hacking {
+    fn name(&self) -> &'static str {
+        "Hacking"
+    }
+
+    fn goal(&self) -> String {
+        format!("hacks {} racks", self.hack_limit)
+    }
+
+    /// There might be fewer racks to hack than the configured limit. There
+    /// is always at least one, as `check_level` turns down levels without
+    fn score_limit(&self, state: &GameState, team_id: u64) -> u32 {
+        let racks = state.props.iter()
+            .filter(|prop| prop.kind == PropKind::ServerRack)
+            .filter(|prop| prop.team.map(|team| team != team_id).unwrap_or(false))
+            .count() as u32;
+        self.hack_limit.min(racks)
+    }
+
+    /// Every team needs enemy racks to hack, or it could never win
+    fn check_level(&self, level: &Level, team_count: usize) -> Result<(), String> {
+        for team_id in 0..team_count as u64 {
+            let has_racks = level.props.iter()
+                .filter(|prop| prop.kind == PropKind::ServerRack)
+                .any(|prop| prop.team.map(|team| team != team_id).unwrap_or(false));
+            if !has_racks {
+                return Err(format!(
+                    "the {} team has no server racks to hack",
+                    TEAMS[team_id as usize].0
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hacks enemy server racks, or repairs the racks of their own team,
+    /// while an agent holds down the interaction key next to them
+    fn update_agent(
+        &self,
+        state: &mut GameState,
+        player_id: u64,
+        input: &ClientInput,
+        delta: f32,
+    ) {
+        if !input.interact {
+            return;
+        }
+        let (team_id, position) = match (
+            state.team_of_player(player_id),
+            state.get_player_by_id(player_id),
+        ) {
+            (Some(team_id), Some(player))
+                if player.player_type == PlayerType::Agent && player.is_alive() =>
+            {
+                (team_id, player.position)
+            }
+            _ => return,
+        };
+        let has_gpu = state.props.iter()
+            .any(|prop| prop.kind == PropKind::Gpu && prop.carrier == Some(player_id));
+        let speed = if has_gpu { GPU_HACK_SPEEDUP } else { 1. } / HACK_TIME;
+
+        // Hacking an enemy rack goes before repairing one of their own
+        let in_reach = |prop: &Prop| (prop.position - position).mag() <= HACK_RADIUS;
+        let own_rack = |prop: &Prop| {
+            prop.kind == PropKind::ServerRack
+                && prop.hacked_by.is_none()
+                && prop.team == Some(team_id)
+        };
+        let rack = state.props.iter()
+            .position(|prop| prop.is_hackable_by(team_id) && in_reach(prop))
+            .or_else(|| state.props.iter().position(|prop| own_rack(prop) && in_reach(prop)));
+        let rack = match rack {
+            Some(index) => &mut state.props[index],
+            None => return,
+        };
+
+        let mut message = None;
+        if rack.is_hackable_by(team_id) {
+            if rack.hacking_team != Some(team_id) {
+                rack.hacking_team = Some(team_id);
+                rack.hack_progress = 0.;
+            }
+            if rack.hack_progress == 0. {
+                message = Some("is hacking a server rack");
+            }
+            rack.hack_progress = (rack.hack_progress + speed * delta).min(1.);
+            if rack.hack_progress >= 1. {
+                rack.hacked_by = Some(team_id);
+                if let Some(team) = state.teams.get_mut(&team_id) {
+                    team.score += 1;
+                }
+                message = Some("hacked a server rack");
+            }
+        } else if rack.team == Some(team_id) {
+            rack.hack_progress = (rack.hack_progress - speed * delta).max(0.);
+        }
+
+        if let Some(message) = message {
+            let message = format!("{} {}", state.player_name(player_id), message);
+            state.killfeed.add_message(&message);
+        }
+    }
+}
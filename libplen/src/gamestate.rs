@@ -1,31 +1,819 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::Receiver;
 
 use serde_derive::{Serialize, Deserialize};
 
+use crate::constants::{
+    COUNTDOWN_DURATION, DEFAULT_SCORE_LIMIT, DEFAULT_TEAM_COUNT, DEFAULT_TIME_LIMIT,
+    EXPLOSION_DAMAGE, EXPLOSION_RADIUS, FLAG_CAPTURE_RADIUS, FLAG_PICKUP_RADIUS, HEARING_DISTANCE,
+    MAX_HITS, MAX_SHOTS, PLAYER_RADIUS, PROP_PICKUP_RADIUS, RESULTS_DURATION, SHOT_DURATION, TEAMS,
+    WEAPON_RANGE,
+};
+use crate::collision;
+use crate::door::{self, Door, DoorState};
+use crate::flag::{Capture, Flag};
+use crate::gamemode::{CaptureTheFlag, GameMode, Mode};
+use crate::killfeed::KillFeed;
+use crate::level::{self, Level, RoomCoord};
 use crate::math::{Vec2, vec2, wrap_around};
-use crate::player::{self, Player};
+use crate::messages::{ClientInput, SoundEffect};
+use crate::player::{self, JoinError, Player, PlayerType};
+use crate::prop::{Prop, PropKind};
+use crate::weapon::{Hit, Shot, WeaponEvent, WeaponKind};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum MatchState {
+    /// Players are picking teams and readying up
+    Lobby,
+    Countdown { time_left: f32 },
+    InProgress { time_left: f32 },
+    Finished { winner: Option<u64>, time_left: f32 },
+}
+
+impl MatchState {
+    /// Short description of the match timer, for displaying in the HUD
+    pub fn clock_text(&self) -> String {
+        match *self {
+            MatchState::Lobby => String::from("Waiting for players"),
+            MatchState::Countdown { time_left } => format!("Starting in {}", time_left.ceil()),
+            MatchState::InProgress { time_left } => {
+                let seconds = time_left.max(0.).ceil() as u32;
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            }
+            MatchState::Finished { .. } => String::from("Match over"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MatchConfig {
+    pub mode: Mode,
+    /// Length of a match in seconds
+    pub time_limit: f32,
+}
+
+impl MatchConfig {
+    pub fn new() -> Self {
+        MatchConfig {
+            mode: CaptureTheFlag { score_limit: DEFAULT_SCORE_LIMIT }.into(),
+            time_limit: DEFAULT_TIME_LIMIT,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GameState {
     pub teams: HashMap<u64, player::Team>,
-    pub game_started: bool,
+    pub match_state: MatchState,
+    pub config: MatchConfig,
+    pub flags: Vec<Flag>,
+    pub captures: Vec<Capture>,
+    pub doors: Vec<Door>,
+    pub props: Vec<Prop>,
+    pub shots: Vec<Shot>,
+    /// The hits of the last tick
+    pub hits: Vec<Hit>,
+    pub killfeed: KillFeed,
     // put server side game state stuff here
 }
 
 impl GameState {
     pub fn new() -> GameState {
+        GameState::with_teams(DEFAULT_TEAM_COUNT)
+    }
+
+    /// A game between the first `team_count` of the `TEAMS`
+    pub fn with_teams(team_count: usize) -> GameState {
         let mut state = GameState {
             teams: HashMap::new(),
-            game_started: false,
+            match_state: MatchState::Lobby,
+            config: MatchConfig::new(),
+            flags: vec![],
+            captures: vec![],
+            doors: vec![],
+            props: vec![],
+            shots: vec![],
+            hits: vec![],
+            killfeed: KillFeed::new(),
         };
-        state.add_team("RED".to_string(), (255, 0, 0));
-        state.add_team("BLUE".to_string(), (0, 0, 255));
+        for &(name, color) in TEAMS.iter().take(team_count) {
+            state.add_team(name.to_string(), color);
+        }
         state
     }
 
-    pub fn update(&mut self, delta: f32) {
+    /// Runs the rules of the game for one tick, with the latest input of
+    /// every client. Returns the sounds that should be played for everyone
+    pub fn update(
+        &mut self,
+        delta: f32,
+        level: &Level,
+        inputs: &HashMap<u64, ClientInput>,
+    ) -> Vec<(SoundEffect, Vec2)> {
         // update game state
+        if self.player_count() == 0 {
+            self.match_state = MatchState::Lobby;
+        }
+
+        for shot in &mut self.shots {
+            shot.time_left -= delta;
+        }
+        self.shots.retain(|shot| shot.time_left > 0.);
+        self.hits.clear();
+        self.killfeed.manage_killfeed(delta);
+
+        let mut sounds = vec![];
+        match self.match_state {
+            MatchState::Lobby => {
+                if self.everyone_ready() {
+                    self.reset_flags(level);
+                    self.reset_doors(level);
+                    self.props = level.props.iter().map(Prop::new).collect();
+                    self.spawn_agents(level);
+                    self.match_state = MatchState::Countdown {
+                        time_left: COUNTDOWN_DURATION,
+                    };
+                }
+            }
+            MatchState::Countdown { time_left } => {
+                if time_left <= 0. {
+                    self.start_match();
+                } else {
+                    self.match_state = MatchState::Countdown {
+                        time_left: time_left - delta,
+                    };
+                }
+            }
+            MatchState::InProgress { time_left } => {
+                let mode = self.config.mode;
+                self.respawn_dead_agents(delta, level);
+                for (&player_id, input) in inputs {
+                    self.update_player(player_id, input, delta, level);
+                    mode.update_agent(self, player_id, input, delta);
+                    sounds.extend(self.update_weapon(player_id, input, delta, level));
+                }
+                self.update_props();
+                mode.update(self, delta, level);
+
+                let leader = self.leading_team();
+                let reached_score_limit = leader
+                    .map(|id| self.teams[&id].score >= self.score_limit(id))
+                    .unwrap_or(false);
+
+                if reached_score_limit || time_left <= 0. {
+                    self.match_state = MatchState::Finished {
+                        winner: leader,
+                        time_left: RESULTS_DURATION,
+                    };
+                } else {
+                    self.match_state = MatchState::InProgress {
+                        time_left: time_left - delta,
+                    };
+                }
+            }
+            MatchState::Finished { winner, time_left } => {
+                if time_left <= 0. {
+                    self.return_to_lobby();
+                } else {
+                    self.match_state = MatchState::Finished {
+                        winner,
+                        time_left: time_left - delta,
+                    };
+                }
+            }
+        }
+        sounds
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        match self.match_state {
+            MatchState::InProgress { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.match_state {
+            MatchState::Finished { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn player_count(&self) -> usize {
+        self.teams.values().map(|team| team.players().count()).sum()
+    }
+
+    fn everyone_ready(&self) -> bool {
+        self.player_count() > 0
+            && self.teams.values().all(|team| team.players().all(|p| p.ready))
+    }
+
+    /// The score that a team needs to win the match
+    pub fn score_limit(&self, team_id: u64) -> u32 {
+        self.config.mode.score_limit(self, team_id)
+    }
+
+    /// The team with the strictly highest score, if there is one
+    fn leading_team(&self) -> Option<u64> {
+        let best = self.teams.values().map(|team| team.score).max()?;
+        let mut leaders = self.teams.values().filter(|team| team.score == best);
+        match (leaders.next(), leaders.next()) {
+            (Some(team), None) => Some(team.id),
+            _ => None,
+        }
+    }
+
+    fn start_match(&mut self) {
+        for team in self.teams.values_mut() {
+            team.score = 0;
+        }
+        for flag in &mut self.flags {
+            flag.return_home();
+        }
+        self.captures.clear();
+        self.shots.clear();
+        self.killfeed = KillFeed::new();
+        self.match_state = MatchState::InProgress {
+            time_left: self.config.time_limit,
+        };
+    }
+
+    fn return_to_lobby(&mut self) {
+        for team in self.teams.values_mut() {
+            for player in team.players_mut() {
+                player.ready = false;
+            }
+        }
+        self.match_state = MatchState::Lobby;
+    }
+
+    pub fn set_player_ready(&mut self, player_id: u64, ready: bool) {
+        if let Some(player) = self.get_mut_player_by_id(player_id) {
+            player.ready = ready;
+        }
+    }
+
+    /// Puts the flag of every team in the middle of its base
+    fn reset_flags(&mut self, level: &Level) {
+        self.flags = self.sorted_teams()
+            .iter()
+            .filter_map(|team| level.base_room(team.id).map(|base| (team.id, base)))
+            .map(|(team_id, (col, row))| Flag::new(team_id, level::room_center(col, row)))
+            .collect();
+    }
+
+    /// Opens every door of the level, with the doors into each base
+    /// belonging to the team whose base it is
+    fn reset_doors(&mut self, level: &Level) {
+        let bases: Vec<_> = self.teams.keys()
+            .filter_map(|&team_id| level.base_room(team_id).map(|base| (team_id, base)))
+            .collect();
+        self.doors = door::level_doors(level, &bases);
+    }
+
+    /// Places every agent at one of their team's spawn points
+    pub(crate) fn spawn_agents(&mut self, level: &Level) {
+        let mut taken = vec![];
+        for team in self.teams.values_mut() {
+            let spawn_points = level.spawn_points(team.id);
+            for agent in &mut team.agents {
+                agent.respawn(pick_spawn_point(&spawn_points, &taken));
+                taken.push(agent.position);
+            }
+        }
+    }
+
+    /// Moves an agent to a free spawn point of their team, for example when
+    /// joining a match that is already running
+    pub fn spawn_player(&mut self, player_id: u64, level: &Level) {
+        let taken: Vec<_> = self.agent_positions()
+            .into_iter()
+            .filter(|&(_, id, _)| id != player_id)
+            .map(|(_, _, position)| position)
+            .collect();
+
+        for team in self.teams.values_mut() {
+            if let Some(agent) = team.agents.iter_mut().find(|agent| agent.id == player_id) {
+                agent.respawn(pick_spawn_point(&level.spawn_points(team.id), &taken));
+            }
+        }
+    }
+
+    /// Counts down the respawn timers of dead agents and respawns the ones
+    /// whose time is up
+    fn respawn_dead_agents(&mut self, delta: f32, level: &Level) {
+        let mut ready = vec![];
+        for team in self.teams.values_mut() {
+            for agent in team.agents.iter_mut().filter(|agent| !agent.is_alive()) {
+                agent.respawn_time -= delta;
+                if agent.respawn_time <= 0. {
+                    ready.push(agent.id);
+                }
+            }
+        }
+        for player_id in ready {
+            self.spawn_player(player_id, level);
+        }
+    }
+
+    /// Starts over after a capture, with the flags back home and everyone at
+    /// their spawn points
+    pub(crate) fn reset_round(&mut self, level: &Level) {
+        for flag in &mut self.flags {
+            flag.return_home();
+        }
+        self.spawn_agents(level);
+    }
+
+    /// Whether one of the living agents of the team is close enough to hear
+    /// a sound at `position`. The dispatcher hears what their agents hear
+    pub fn can_hear(&self, team_id: Option<u64>, position: Vec2) -> bool {
+        self.agent_positions()
+            .iter()
+            .any(|&(team, _, agent)| {
+                Some(team) == team_id && (agent - position).mag() <= HEARING_DISTANCE
+            })
+    }
+
+    /// Team id, player id and position of every living agent in the game
+    fn agent_positions(&self) -> Vec<(u64, u64, Vec2)> {
+        self.teams.values()
+            .flat_map(|team| {
+                team.agents.iter()
+                    .filter(|agent| agent.is_alive())
+                    .map(move |agent| (team.id, agent.id, agent.position))
+            })
+            .collect()
+    }
+
+    /// Moves the flags along with the agents, returning true if a flag was
+    /// captured
+    pub(crate) fn update_flags(&mut self) -> bool {
+        let agents = self.agent_positions();
+        let mut captured = false;
+        let mut messages = vec![];
+
+        // Carried flags follow their carrier, and are dropped if the carrier
+        // is no longer around
+        for flag in &mut self.flags {
+            if let Some(carrier) = flag.carrier {
+                match agents.iter().find(|(_, id, _)| *id == carrier) {
+                    Some((_, _, position)) => flag.position = *position,
+                    None => flag.drop(),
+                }
+            }
+        }
+
+        for &(team_id, player_id, position) in &agents {
+            let is_carrying = self.flags.iter().any(|f| f.carrier == Some(player_id));
+
+            for flag in &mut self.flags {
+                if flag.carrier.is_some()
+                    || (flag.position - position).mag() > FLAG_PICKUP_RADIUS
+                {
+                    continue;
+                }
+
+                if flag.team_id == team_id {
+                    // Touching your own flag returns it to base
+                    if !flag.is_home() {
+                        flag.return_home();
+                    }
+                } else if !is_carrying {
+                    flag.carrier = Some(player_id);
+                    flag.position = position;
+                    messages.push((player_id, flag.team_id, "took"));
+                    break;
+                }
+            }
+
+            let own_flag_home = self.flags.iter()
+                .find(|f| f.team_id == team_id)
+                .filter(|f| f.is_home())
+                .map(|f| f.home);
+
+            if let Some(home) = own_flag_home {
+                if (position - home).mag() > FLAG_CAPTURE_RADIUS {
+                    continue;
+                }
+                for flag in &mut self.flags {
+                    if flag.carrier == Some(player_id) {
+                        flag.return_home();
+                        messages.push((player_id, flag.team_id, "captured"));
+                        self.captures.push(Capture {
+                            team_id,
+                            player_id,
+                            flag_team_id: flag.team_id,
+                        });
+                        if let Some(team) = self.teams.get_mut(&team_id) {
+                            team.score += 1;
+                        }
+                        captured = true;
+                    }
+                }
+            }
+        }
+
+        for (player_id, flag_team_id, verb) in messages {
+            let message = format!(
+                "{} {} the {} flag",
+                self.player_name(player_id),
+                verb,
+                self.team_name(flag_team_id),
+            );
+            self.killfeed.add_message(&message);
+        }
+
+        captured
+    }
+
+    /// Lets agents pick up GPUs and carry them around
+    fn update_props(&mut self) {
+        let agents = self.agent_positions();
+        let mut messages = vec![];
+
+        for prop in self.props.iter_mut().filter(|prop| prop.kind == PropKind::Gpu) {
+            if let Some(carrier) = prop.carrier {
+                match agents.iter().find(|(_, id, _)| *id == carrier) {
+                    Some((_, _, position)) => prop.position = *position,
+                    None => prop.carrier = None,
+                }
+            }
+        }
+
+        for &(_, player_id, position) in &agents {
+            if self.props.iter().any(|prop| prop.carrier == Some(player_id)) {
+                continue;
+            }
+            let free_gpu = self.props.iter_mut().find(|prop| {
+                prop.kind == PropKind::Gpu
+                    && prop.carrier.is_none()
+                    && (prop.position - position).mag() <= PROP_PICKUP_RADIUS
+            });
+            if let Some(prop) = free_gpu {
+                prop.carrier = Some(player_id);
+                messages.push(player_id);
+            }
+        }
+
+        for player_id in messages {
+            let message = format!("{} picked up a GPU", self.player_name(player_id));
+            self.killfeed.add_message(&message);
+        }
+    }
+
+    /// Sends the GPUs that agents have carried into their base back home and
+    /// gives the team a point for each. No mode scores GPUs yet, a mode that
+    /// wants them to count calls this from its `update`
+    pub fn deliver_gpus(&mut self, level: &Level) {
+        let mut delivered = vec![];
+        for (team_id, player_id, position) in self.agent_positions() {
+            let in_base = level.base_room(team_id)
+                .map(|(col, row)| level::room_bounds(col, row).contains(position))
+                .unwrap_or(false);
+            if !in_base {
+                continue;
+            }
+            for prop in self.props.iter_mut().filter(|prop| prop.carrier == Some(player_id)) {
+                prop.return_home();
+                delivered.push((team_id, player_id));
+            }
+        }
+
+        for (team_id, player_id) in delivered {
+            if let Some(team) = self.teams.get_mut(&team_id) {
+                team.score += 1;
+            }
+            let message = format!("{} delivered a GPU", self.player_name(player_id));
+            self.killfeed.add_message(&message);
+        }
+    }
+
+    /// Moves and turns a player according to their input
+    fn update_player(
+        &mut self,
+        player_id: u64,
+        input: &ClientInput,
+        delta: f32,
+        level: &Level,
+    ) {
+        let doors = &self.doors;
+        for team in self.teams.values_mut() {
+            if let Some(player) = team.players_mut().find(|player| player.id == player_id) {
+                player.update(delta, input, level, doors);
+            }
+        }
+    }
+
+    /// Opens, closes or locks a door on behalf of a dispatcher. Returns false
+    /// if the dispatcher isn't allowed to, or if someone is standing in the
+    /// way of the door
+    pub fn set_door(
+        &mut self,
+        player_id: u64,
+        room: RoomCoord,
+        direction: (i8, i8),
+        state: DoorState,
+    ) -> bool {
+        let is_dispatcher = self.get_player_by_id(player_id)
+            .map(|player| player.player_type == PlayerType::Dispatcher)
+            .unwrap_or(false);
+        let team_id = match self.team_of_player(player_id) {
+            Some(team_id) if is_dispatcher => team_id,
+            _ => return false,
+        };
+        let target = match level::door_target(room, direction) {
+            Some(target) => target,
+            None => return false,
+        };
+        let agents = self.agent_positions();
+        let door = match self.doors.iter_mut().find(|door| door.connects(room, target)) {
+            Some(door) if door.can_change(team_id, state) => door,
+            _ => return false,
+        };
+
+        if state != DoorState::Open {
+            let bounds = door.bounds().shrunk(vec2(-PLAYER_RADIUS, -PLAYER_RADIUS));
+            if agents.iter().any(|&(_, _, position)| bounds.contains(position)) {
+                return false;
+            }
+        }
+        if state == DoorState::Locked {
+            door.owner = Some(team_id);
+        }
+        door.state = state;
+        true
+    }
+
+    /// Charges and fires the weapon of an agent according to their input.
+    /// Returns the sounds that should be played for everyone
+    fn update_weapon(
+        &mut self,
+        player_id: u64,
+        input: &ClientInput,
+        delta: f32,
+        level: &Level,
+    ) -> Vec<(SoundEffect, Vec2)> {
+        let player = match self.get_mut_player_by_id(player_id) {
+            Some(player) if player.player_type == PlayerType::Agent && player.is_alive() => {
+                player
+            }
+            _ => return vec![],
+        };
+        player.weapon.select(input.weapon);
+        let event = player.weapon.update(delta, input.fire);
+        let (kind, position, direction) = (player.weapon.kind, player.position, player.facing());
+
+        match event {
+            Some(WeaponEvent::ChargeStarted) => vec![(SoundEffect::LaserCharge, position)],
+            Some(WeaponEvent::Fired) => {
+                let explosions = self.fire(player_id, kind, direction, level);
+                let mut sounds = vec![(kind.sound(), position)];
+                sounds.extend(explosions.into_iter().map(|at| (SoundEffect::Explosion, at)));
+                sounds
+            }
+            None => vec![],
+        }
+    }
+
+    /// Shoots along `direction` until the shot hits a wall. The gun stops at
+    /// the first enemy or barrel in the way, the laser goes through all of
+    /// them. Returns where barrels blew up
+    fn fire(
+        &mut self,
+        shooter: u64,
+        kind: WeaponKind,
+        direction: Vec2,
+        level: &Level,
+    ) -> Vec<Vec2> {
+        let (team_id, from) = match (
+            self.team_of_player(shooter),
+            self.get_player_by_id(shooter),
+        ) {
+            (Some(team_id), Some(player)) => (team_id, player.position),
+            _ => return vec![],
+        };
+        let range = collision::raycast(level, &self.doors, from, direction, WEAPON_RANGE);
+
+        // How far along the shot `position` is, if something of the given
+        // radius there is in the line of fire
+        let along_shot = |position: Vec2, radius: f32| {
+            let offset = position - from;
+            let along = offset.dot(direction);
+            let miss = (offset - direction * along).mag();
+            if along > 0. && along <= range && miss <= radius {
+                Some(along)
+            } else {
+                None
+            }
+        };
+
+        let enemies = self.teams.values()
+            .filter(|team| team.id != team_id)
+            .flat_map(|team| team.agents.iter())
+            .filter(|agent| agent.is_alive())
+            .filter_map(|agent| {
+                along_shot(agent.position, PLAYER_RADIUS)
+                    .map(|along| (along, Target::Agent(agent.id)))
+            });
+        let barrels = self.props.iter()
+            .enumerate()
+            .filter(|(_, prop)| prop.health.is_some() && !prop.is_destroyed())
+            .filter_map(|(index, prop)| {
+                along_shot(prop.position, prop.kind.radius())
+                    .map(|along| (along, Target::Prop(index)))
+            });
+        let mut targets: Vec<(f32, Target)> = enemies.chain(barrels).collect();
+        targets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut end = range;
+        if kind == WeaponKind::Gun {
+            targets.truncate(1);
+            if let Some(&(along, _)) = targets.first() {
+                end = along;
+            }
+        }
+
+        let damage = kind.damage();
+        let mut explosions = vec![];
+        for (_, target) in targets {
+            match target {
+                Target::Agent(target) => {
+                    let cause = format!("the {}", kind.name());
+                    self.damage_agent(Hit { shooter, target, kind, damage }, &cause);
+                }
+                Target::Prop(index) => {
+                    let prop = &mut self.props[index];
+                    prop.health = prop.health.map(|health| (health - damage).max(0.));
+                    if prop.is_destroyed() {
+                        explosions.push(prop.position);
+                    }
+                }
+            }
+        }
+
+        // Explosions hurt everyone close enough, no matter the team
+        for &at in &explosions {
+            let victims: Vec<_> = self.agent_positions()
+                .into_iter()
+                .filter(|&(_, _, position)| (position - at).mag() <= EXPLOSION_RADIUS)
+                .map(|(_, id, _)| id)
+                .collect();
+            for target in victims {
+                let hit = Hit { shooter, target, kind, damage: EXPLOSION_DAMAGE };
+                self.damage_agent(hit, "an exploding barrel");
+            }
+        }
+
+        self.shots.push(Shot {
+            shooter,
+            kind,
+            from,
+            to: from + direction * end,
+            time_left: SHOT_DURATION,
+        });
+        if self.shots.len() > MAX_SHOTS {
+            self.shots.remove(0);
+        }
+        explosions
+    }
+
+    /// Takes the damage of a hit off the target, starting their respawn
+    /// timer if it kills them. `cause` is what the kill feed says they were
+    /// killed with
+    fn damage_agent(&mut self, hit: Hit, cause: &str) {
+        let mode = self.config.mode;
+        let killed = match self.get_mut_player_by_id(hit.target) {
+            Some(agent) if agent.is_alive() => {
+                agent.health = (agent.health - hit.damage).max(0.);
+                if !agent.is_alive() {
+                    agent.respawn_time = mode.respawn_time();
+                }
+                !agent.is_alive()
+            }
+            _ => return,
+        };
+        self.hits.push(hit);
+        if self.hits.len() > MAX_HITS {
+            self.hits.remove(0);
+        }
+
+        if killed {
+            let message = format!(
+                "{} killed {} with {}",
+                self.player_name(hit.shooter),
+                self.player_name(hit.target),
+                cause,
+            );
+            self.killfeed.add_message(&message);
+            mode.on_kill(self, hit);
+        }
+    }
+
+    /// Ids of the players that the team knows the whereabouts of: its own
+    /// players, enemies that one of its agents has a line of sight to and
+    /// anyone carrying its flag
+    pub fn players_seen_by(&self, team_id: u64, level: &Level) -> HashSet<u64> {
+        let agents = self.agent_positions();
+        let own_agents: Vec<_> = agents.iter()
+            .filter(|(team, _, _)| *team == team_id)
+            .map(|&(_, _, position)| position)
+            .collect();
+        let own_flag_carrier = self.flags.iter()
+            .find(|flag| flag.team_id == team_id)
+            .and_then(|flag| flag.carrier);
+
+        let mut seen: HashSet<_> = self.teams.get(&team_id)
+            .map(|team| team.players().map(|player| player.id).collect())
+            .unwrap_or_default();
+        for &(_, id, position) in &agents {
+            let in_sight = || {
+                own_agents.iter()
+                    .any(|&own| collision::line_of_sight(level, &self.doors, own, position))
+            };
+            if own_flag_carrier == Some(id) || in_sight() {
+                seen.insert(id);
+            }
+        }
+        seen
+    }
+
+    /// A copy of the state to send to the members of a team, with the
+    /// positions of everyone the team can't see hidden. Players that are
+    /// not in a team don't get to see any agents.
+    pub fn snapshot_for(&self, team_id: Option<u64>, level: &Level) -> GameState {
+        let seen = team_id
+            .map(|team_id| self.players_seen_by(team_id, level))
+            .unwrap_or_default();
+
+        let mut snapshot = self.clone();
+        for team in snapshot.teams.values_mut() {
+            for agent in &mut team.agents {
+                if !seen.contains(&agent.id) {
+                    agent.visible = false;
+                    agent.position = Vec2::zero();
+                    agent.rotation = 0.;
+                }
+            }
+        }
+        // A carried flag would give away where its carrier is
+        for flag in &mut snapshot.flags {
+            if flag.carrier.map(|id| !seen.contains(&id)).unwrap_or(false) {
+                flag.position = Vec2::zero();
+            }
+        }
+        for prop in &mut snapshot.props {
+            if prop.carrier.map(|id| !seen.contains(&id)).unwrap_or(false) {
+                prop.position = Vec2::zero();
+            }
+        }
+        snapshot.shots.retain(|shot| seen.contains(&shot.shooter));
+        snapshot.hits.retain(|hit| seen.contains(&hit.shooter) || seen.contains(&hit.target));
+        snapshot
+    }
+
+    /// The flags that are not being carried by someone hidden from view.
+    /// Flags are only used in modes that have them
+    pub fn visible_flags(&self) -> impl Iterator<Item = &Flag> {
+        let has_flags = self.config.mode.has_flags();
+        self.flags.iter().filter(move |flag| has_flags && match flag.carrier {
+            Some(carrier) => self.get_player_by_id(carrier)
+                .map(|player| player.visible)
+                .unwrap_or(false),
+            None => true,
+        })
+    }
+
+    /// The props that are still around and not carried by someone hidden
+    /// from view
+    pub fn visible_props(&self) -> impl Iterator<Item = &Prop> {
+        self.props.iter().filter(move |prop| {
+            let carrier_visible = match prop.carrier {
+                Some(carrier) => self.get_player_by_id(carrier)
+                    .map(|player| player.visible)
+                    .unwrap_or(false),
+                None => true,
+            };
+            carrier_visible && !prop.is_destroyed()
+        })
+    }
+
+    pub fn team_of_player(&self, player_id: u64) -> Option<u64> {
+        self.teams.values()
+            .find(|team| team.has_player(player_id))
+            .map(|team| team.id)
+    }
+
+    pub(crate) fn player_name(&self, player_id: u64) -> String {
+        self.get_player_by_id(player_id)
+            .map(|player| player.name.clone())
+            .unwrap_or_default()
+    }
+
+    fn team_name(&self, team_id: u64) -> String {
+        self.teams.get(&team_id)
+            .map(|team| team.name.clone())
+            .unwrap_or_default()
     }
 
     pub fn set_player_name(&mut self, player_id: u64, name: String) {
@@ -61,34 +849,275 @@ impl GameState {
         None
     }
 
-    pub fn try_add_player_to_team(
-        &mut self, player_id: u64, team_id: u64, player_type: player::PlayerType, name: String
-     ) {
-        for (id, team) in &mut self.teams {
-            if team.has_player(player_id) {
-                team.remove_player(player_id);
-                if team_id == *id {
-                    team.try_add_player(player_id, name.clone(), player_type);
-                }
-            } else {
-                if team_id == *id {
-                    team.try_add_player(player_id, name.clone(), player_type);
+    /// Puts a player in a team, taking them out of the one they were in.
+    /// Without a `team_id`, the server picks the team that the player evens
+    /// out the most. With `queue`, players who want to be the dispatcher of
+    /// a team that already has one play as agents of the team while they
+    /// wait in line for the role, instead of being turned down. Returns the
+    /// team that the player ended up in
+    pub fn join_team(
+        &mut self,
+        player_id: u64,
+        team_id: Option<u64>,
+        player_type: PlayerType,
+        name: String,
+        queue: bool,
+    ) -> Result<u64, JoinError> {
+        let team_id = match team_id {
+            Some(team_id) => team_id,
+            None => self.balanced_team(player_id, player_type, queue)?,
+        };
+        let team = self.teams.get(&team_id).ok_or(JoinError::NoSuchTeam)?;
+
+        if player_type == PlayerType::Dispatcher && team.is_dispatcher(player_id) {
+            return Ok(team_id);
+        }
+        let must_queue = player_type == PlayerType::Dispatcher && team.dispatcher.is_some();
+        if must_queue && !queue {
+            return Err(JoinError::DispatcherTaken);
+        }
+
+        if must_queue && team.is_agent(player_id) {
+            let team = self.teams.get_mut(&team_id).ok_or(JoinError::NoSuchTeam)?;
+            team.queue_dispatcher(player_id);
+            return Ok(team_id);
+        }
+
+        self.remove_player(player_id);
+        let team = self.teams.get_mut(&team_id).ok_or(JoinError::NoSuchTeam)?;
+        if must_queue {
+            team.try_add_player(player_id, name, PlayerType::Agent)?;
+            team.queue_dispatcher(player_id);
+        } else {
+            team.try_add_player(player_id, name, player_type)?;
+        }
+        Ok(team_id)
+    }
+
+    /// The team with the fewest players. Dispatchers go to the smallest team
+    /// that doesn't have a dispatcher, or with `queue`, to the team with the
+    /// shortest line for the role if every team has one
+    fn balanced_team(
+        &self,
+        player_id: u64,
+        player_type: PlayerType,
+        queue: bool,
+    ) -> Result<u64, JoinError> {
+        let size = |team: &player::Team| team.players().filter(|p| p.id != player_id).count();
+        let teams = self.sorted_teams();
+
+        let team = match player_type {
+            PlayerType::Agent => teams.iter().min_by_key(|team| size(team)),
+            PlayerType::Dispatcher => {
+                let free_team = teams.iter()
+                    .filter(|team| !team.has_other_dispatcher(player_id))
+                    .min_by_key(|team| size(team));
+                match free_team {
+                    Some(team) => Some(team),
+                    None if queue => teams.iter()
+                        .min_by_key(|team| (team.dispatcher_queue.len(), size(team))),
+                    None => return Err(JoinError::NoDispatcherSlots),
                 }
             }
-        }
+        };
+        team.map(|team| team.id).ok_or(JoinError::NoSuchTeam)
     }
 
+    /// Takes the player out of their team and any line for a dispatcher
+    /// role. If they were a dispatcher, someone else takes over
     pub fn remove_player(&mut self, player_id: u64) {
-        for (_, team) in &mut self.teams {
-            if team.has_player(player_id) {
-                team.remove_player(player_id);
-                return;
+        let mid_match = match self.match_state {
+            MatchState::Countdown { .. } | MatchState::InProgress { .. } => true,
+            MatchState::Lobby | MatchState::Finished { .. } => false,
+        };
+
+        let mut new_dispatchers = vec![];
+        for team in self.teams.values_mut() {
+            let was_dispatcher = team.is_dispatcher(player_id);
+            team.remove_player(player_id);
+            if was_dispatcher {
+                new_dispatchers.extend(team.hand_off_dispatcher(mid_match));
+            }
+        }
+
+        if mid_match {
+            for id in new_dispatchers {
+                let message = format!("{} took over as dispatcher", self.player_name(id));
+                self.killfeed.add_message(&message);
             }
         }
     }
 
+    /// All teams, ordered by their id
+    pub fn sorted_teams(&self) -> Vec<&player::Team> {
+        let mut teams: Vec<_> = self.teams.values().collect();
+        teams.sort_by_key(|team| team.id);
+        teams
+    }
+
     pub fn add_team(&mut self, name: String, color: (u8, u8, u8)) {
         let id = self.teams.len() as _;
         self.teams.insert(id, player::Team::new(id, name, color));
     }
 }
+
+/// The first spawn point that nobody is standing on, or the one furthest
+/// away from everyone if they are all taken
+fn pick_spawn_point(spawn_points: &[Vec2], taken: &[Vec2]) -> Vec2 {
+    let clearance = |point: Vec2| {
+        taken.iter()
+            .map(|&other| (other - point).mag())
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    spawn_points.iter()
+        .copied()
+        .find(|&point| clearance(point) >= 2. * PLAYER_RADIUS)
+        .or_else(|| {
+            spawn_points.iter().copied().max_by(|&a, &b| {
+                clearance(a).partial_cmp(&clearance(b)).unwrap()
+            })
+        })
+        .expect("Every team has spawn points")
+}
+
+/// Something that a shot can hit
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Agent(u64),
+    /// Index into `GameState::props`
+    Prop(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn team_ids(state: &GameState) -> Vec<u64> {
+        state.sorted_teams().iter().map(|team| team.id).collect()
+    }
+
+    fn join(
+        state: &mut GameState,
+        player_id: u64,
+        team_id: Option<u64>,
+        player_type: PlayerType,
+        queue: bool,
+    ) -> Result<u64, JoinError> {
+        state.join_team(player_id, team_id, player_type, format!("{}", player_id), queue)
+    }
+
+    #[test]
+    fn auto_assign_picks_the_smallest_team() {
+        let mut state = GameState::with_teams(3);
+        let teams = team_ids(&state);
+        join(&mut state, 1, Some(teams[0]), PlayerType::Agent, false).unwrap();
+        join(&mut state, 2, Some(teams[0]), PlayerType::Agent, false).unwrap();
+        join(&mut state, 3, Some(teams[1]), PlayerType::Agent, false).unwrap();
+
+        assert_eq!(join(&mut state, 4, None, PlayerType::Agent, false), Ok(teams[2]));
+        assert_eq!(join(&mut state, 5, None, PlayerType::Agent, false), Ok(teams[1]));
+    }
+
+    #[test]
+    fn second_dispatcher_is_rejected() {
+        let mut state = GameState::with_teams(2);
+        let teams = team_ids(&state);
+        join(&mut state, 1, Some(teams[0]), PlayerType::Dispatcher, false).unwrap();
+
+        assert_eq!(
+            join(&mut state, 2, Some(teams[0]), PlayerType::Dispatcher, false),
+            Err(JoinError::DispatcherTaken),
+        );
+        assert_eq!(join(&mut state, 2, None, PlayerType::Dispatcher, false), Ok(teams[1]));
+        assert_eq!(
+            join(&mut state, 3, None, PlayerType::Dispatcher, false),
+            Err(JoinError::NoDispatcherSlots),
+        );
+        assert_eq!(
+            join(&mut state, 3, Some(42), PlayerType::Agent, false),
+            Err(JoinError::NoSuchTeam),
+        );
+        assert_eq!(state.team_of_player(3), None);
+    }
+
+    #[test]
+    fn queued_player_plays_as_agent_until_the_dispatcher_leaves() {
+        let mut state = GameState::with_teams(2);
+        let teams = team_ids(&state);
+        join(&mut state, 1, Some(teams[0]), PlayerType::Dispatcher, false).unwrap();
+        join(&mut state, 2, Some(teams[0]), PlayerType::Dispatcher, true).unwrap();
+        join(&mut state, 3, Some(teams[0]), PlayerType::Dispatcher, true).unwrap();
+
+        let team = &state.teams[&teams[0]];
+        assert!(team.is_agent(2));
+        assert_eq!(team.queue_position(2), Some(0));
+        assert_eq!(team.queue_position(3), Some(1));
+        assert_eq!(state.player_count(), 3);
+
+        state.remove_player(1);
+        let team = &state.teams[&teams[0]];
+        assert!(team.is_dispatcher(2));
+        assert!(!team.is_agent(2));
+        assert_eq!(team.queue_position(3), Some(0));
+    }
+
+    #[test]
+    fn agent_takes_over_dispatcher_only_mid_match() {
+        let mut state = GameState::with_teams(2);
+        let teams = team_ids(&state);
+        join(&mut state, 1, Some(teams[0]), PlayerType::Dispatcher, false).unwrap();
+        join(&mut state, 2, Some(teams[0]), PlayerType::Agent, false).unwrap();
+        join(&mut state, 3, Some(teams[0]), PlayerType::Agent, false).unwrap();
+
+        state.remove_player(1);
+        assert!(state.teams[&teams[0]].dispatcher.is_none());
+
+        join(&mut state, 1, Some(teams[0]), PlayerType::Dispatcher, false).unwrap();
+        state.match_state = MatchState::InProgress { time_left: 10. };
+        state.remove_player(1);
+        let team = &state.teams[&teams[0]];
+        assert!(team.is_dispatcher(2));
+        assert_eq!(team.agents.len(), 1);
+
+        // The last agent of a team stays an agent
+        state.remove_player(2);
+        assert!(state.teams[&teams[0]].dispatcher.is_none());
+    }
+
+    #[test]
+    fn other_teams_start_hacking_over() {
+        use crate::gamemode::Hacking;
+        use crate::prop::PropPlacement;
+
+        let mut state = GameState::with_teams(3);
+        let teams = team_ids(&state);
+        let mode: Mode = Hacking { hack_limit: 1 }.into();
+        state.config.mode = mode;
+        let kind = PropKind::ServerRack;
+        let rack = PropPlacement { kind, room: (0, 0), offset: (0., 0.), team: Some(teams[2]) };
+        state.props = vec![Prop::new(&rack)];
+        join(&mut state, 1, Some(teams[0]), PlayerType::Agent, false).unwrap();
+        join(&mut state, 2, Some(teams[1]), PlayerType::Agent, false).unwrap();
+        for player_id in 1..=2 {
+            state.get_mut_player_by_id(player_id).unwrap().position = rack.position();
+        }
+        let input = ClientInput { interact: true, ..ClientInput::new() };
+
+        mode.update_agent(&mut state, 1, &input, 2.);
+        mode.update_agent(&mut state, 1, &input, 2.);
+        mode.update_agent(&mut state, 2, &input, 2.);
+        assert_eq!(state.props[0].hacking_team, Some(teams[1]));
+        assert!(state.props[0].hack_progress < 0.3);
+
+        mode.update_agent(&mut state, 2, &input, 10.);
+        assert_eq!(state.props[0].hacked_by, Some(teams[1]));
+    }
+
+    #[test]
+    fn hacking_needs_racks_to_hack() {
+        let mode: Mode = crate::gamemode::Hacking { hack_limit: 2 }.into();
+        assert!(mode.check_level(&level::generate_level(1, 2), 2).is_ok());
+        assert!(mode.check_level(&level::generate_level(1, 1), 1).is_err());
+    }
+}
@@ -51,11 +51,9 @@ impl KillFeed {
         self.messages.push(Message::new(message.to_string()));
     }
 
-    pub fn get_messages(&mut self) -> Vec<Message> {
-        if self.messages.len() >= 5 {
-            self.messages[..4].to_vec()
-        } else {
-            self.messages[..].to_vec()
-        }
+    /// The most recent messages, oldest first
+    pub fn get_messages(&self) -> &[Message] {
+        let start = self.messages.len().saturating_sub(constants::KILLFEED_LENGTH);
+        &self.messages[start..]
     }
 }
@@ -1,12 +1,54 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
 use serde_derive::{Serialize, Deserialize};
 
-use crate::constants::{ROOM_WIDTH, ROOM_LENGTH, DOORWAY_LENGTH, DOOR_WIDTH};
-use crate::math::{Vec2, vec2};
+use crate::collision;
+use crate::constants::{
+    ROOM_WIDTH, ROOM_LENGTH, DOORWAY_LENGTH, DOOR_WIDTH, CORRIDOR_WIDTH, PLAYER_RADIUS,
+};
+use crate::math::{Vec2, vec2, Rect};
+use crate::prop::{PropKind, PropPlacement};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use ultraviolet::Mat2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Level {
     pub rooms: [Vec<Room>; 8],
+    /// The base room of each team, in the order of the team ids. Levels
+    /// that leave them out have two bases, see `DEFAULT_BASES`
+    #[serde(default)]
+    pub bases: Vec<RoomCoord>,
+    /// Where the players of each team enter the level. Teams that are left
+    /// out spawn around the flag in their base room
+    #[serde(default)]
+    pub spawns: HashMap<u64, Vec<SpawnPoint>>,
+    #[serde(default)]
+    pub props: Vec<PropPlacement>,
+    /// Built from the rooms and props the first time it is needed, so they
+    /// must not change after that
+    #[serde(skip)]
+    pub(crate) collision_areas: OnceLock<collision::CollisionAreas>,
+}
+
+/// A place for a player to spawn at, relative to the centre of a room
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpawnPoint {
+    pub room: RoomCoord,
+    pub offset: (f32, f32),
+}
+
+impl SpawnPoint {
+    pub fn position(&self) -> Vec2 {
+        let (col, row) = self.room;
+        room_center(col, row) + vec2(self.offset.0, self.offset.1)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +58,11 @@ pub enum Room {
 
 pub type Door = Vec<(i8, i8)>;
 
+/// Column and row of a room in a level
+pub type RoomCoord = (usize, usize);
+
+pub const COLUMN_COUNT: usize = 8;
+
 pub fn rooms_in_col(col: usize) -> usize {
     match col {
         0 | 7 => 1,
@@ -39,6 +86,19 @@ pub fn room_corner_position(col: usize, row: usize) -> Vec2 {
     vec2(x, y)
 }
 
+pub fn room_center(col: usize, row: usize) -> Vec2 {
+    room_corner_position(col, row) + vec2(ROOM_WIDTH / 2., ROOM_LENGTH / 2.)
+}
+
+pub fn room_bounds(col: usize, row: usize) -> Rect {
+    let corner = room_corner_position(col, row);
+    Rect::from_corners(corner, corner + vec2(ROOM_WIDTH, ROOM_LENGTH))
+}
+
+/// Bases of levels that don't list their own: the first team starts in the
+/// leftmost column and the second one in the rightmost one
+const DEFAULT_BASES: [RoomCoord; 2] = [(0, 0), (7, 0)];
+
 pub fn doorway_transform((col, row): (usize, usize), (dx, dy): (i8, i8)) -> (Mat2, Vec2) {
     let delta = (dx, dy);
     match delta {
@@ -67,8 +127,7 @@ pub fn doorway_transform((col, row): (usize, usize), (dx, dy): (i8, i8)) -> (Mat
 }
 
 pub fn doorway_bounds((col, row): (usize, usize), (dx, dy): (i8, i8)) -> (Vec2, Vec2) {
-    let this_pos = room_corner_position(col, row);
-    let room_center = this_pos + vec2(ROOM_WIDTH / 2., ROOM_LENGTH / 2.);
+    let room_center = room_center(col, row);
     let (rotation, translation) = doorway_transform((col, row), (dx, dy));
     let door_pos = room_center + translation;
 
@@ -81,54 +140,784 @@ pub fn doorway_bounds((col, row): (usize, usize), (dx, dy): (i8, i8)) -> (Vec2,
     )
 }
 
+/// The parts of a corridor that are not solid: a strip from the middle of
+/// the room to each door. Doors in the side walls are reached by going along
+/// the length of the room first and then turning the corner towards the door.
+pub fn corridor_areas(room: RoomCoord, doors: &[(i8, i8)]) -> Vec<Rect> {
+    let center = room_center(room.0, room.1);
+    let half = CORRIDOR_WIDTH / 2.;
+
+    let mut areas = vec![];
+    for &door in doors {
+        let door_pos = center + doorway_transform(room, door).1;
+        match door {
+            (0, dy) => {
+                let dy = dy as f32;
+                areas.push(Rect::from_corners(
+                    center - vec2(half, half * dy),
+                    door_pos + vec2(half, 0.),
+                ));
+            }
+            (dx, _) => {
+                let dx = dx as f32;
+                let dy = (door_pos.y - center.y).signum();
+                areas.push(Rect::from_corners(
+                    center - vec2(half, half * dy),
+                    vec2(center.x + half, door_pos.y + half * dy),
+                ));
+                areas.push(Rect::from_corners(
+                    vec2(center.x - half * dx, door_pos.y - half),
+                    door_pos + vec2(0., half),
+                ));
+            }
+        }
+    }
+    areas
+}
+
+/// Spawn points used for teams that the level does not give any, spread out
+/// around the flag in the middle of the base room
+const DEFAULT_SPAWN_OFFSETS: [(f32, f32); 6] = [
+    (-0.75, -1.5), (0.75, -1.5),
+    (-0.75, 1.5), (0.75, 1.5),
+    (-0.75, 0.), (0.75, 0.),
+];
+
+/// The room a door from `room` leads to, or None if it leads outside the
+/// level grid
+pub fn door_target((col, row): RoomCoord, (dx, dy): (i8, i8)) -> Option<RoomCoord> {
+    let col = col as i64 + dx as i64;
+    let row = row as i64 + dy as i64;
+    if col < 0 || col >= COLUMN_COUNT as i64 || row < 0 {
+        return None;
+    }
+    let (col, row) = (col as usize, row as usize);
+    if row < rooms_in_col(col) {
+        Some((col, row))
+    } else {
+        None
+    }
+}
+
+impl Level {
+    pub fn room(&self, (col, row): RoomCoord) -> Option<&Room> {
+        self.rooms.get(col)?.get(row)
+    }
+
+    /// Where in the level things can be, for collisions and lines of sight
+    pub fn collision_areas(&self) -> &collision::CollisionAreas {
+        self.collision_areas.get_or_init(|| collision::CollisionAreas::new(self))
+    }
+
+    /// The base rooms of the teams, indexed by team id. The level can be
+    /// played by at most this many teams
+    pub fn bases(&self) -> &[RoomCoord] {
+        if self.bases.is_empty() {
+            &DEFAULT_BASES
+        } else {
+            &self.bases
+        }
+    }
+
+    /// The room in which a team has its flag, if the level has room for the
+    /// team
+    pub fn base_room(&self, team_id: u64) -> Option<RoomCoord> {
+        self.bases().get(team_id as usize).cloned()
+    }
+
+    pub fn spawn_points(&self, team_id: u64) -> Vec<Vec2> {
+        match (self.spawns.get(&team_id), self.base_room(team_id)) {
+            (Some(spawns), _) if !spawns.is_empty() => {
+                spawns.iter().map(SpawnPoint::position).collect()
+            }
+            (_, Some(room)) => {
+                DEFAULT_SPAWN_OFFSETS.iter()
+                    .map(|&offset| SpawnPoint { room, offset }.position())
+                    .collect()
+            }
+            (_, None) => vec![],
+        }
+    }
+}
+
+impl Room {
+    pub fn doors(&self) -> &[(i8, i8)] {
+        match self {
+            FullRoom(doors) | Corridor(doors) => doors,
+            Empty => &[],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Empty => true,
+            _ => false,
+        }
+    }
+}
+
+/// Whether a door offset points at one of the eight surrounding grid cells
+fn is_door_direction((dx, dy): (i8, i8)) -> bool {
+    (dx, dy) != (0, 0) && dx.abs() <= 1 && dy.abs() <= 1
+}
+
+/// Whether the walls of two rooms in neighbouring columns overlap enough
+/// for a door to fit between them
+fn is_aligned(room: RoomCoord, target: RoomCoord) -> bool {
+    let offset = room_center(target.0, target.1) - room_center(room.0, room.1);
+    room.0 == target.0 || offset.y.abs() <= ROOM_LENGTH - DOOR_WIDTH
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelError {
+    /// The column does not have the number of rooms that the
+    /// 1-2-3-4-4-3-2-1 shape requires
+    WrongColumnLength { col: usize, expected: usize, found: usize },
+    /// The door offset is not one of the eight neighbouring directions
+    InvalidDoor { room: RoomCoord, door: (i8, i8) },
+    /// The door leads outside the level
+    DoorOutOfBounds { room: RoomCoord, door: (i8, i8) },
+    /// The door leads to a room in the next column whose walls are too far
+    /// away to connect to
+    MisalignedDoor { room: RoomCoord, door: (i8, i8) },
+    DoorIntoEmpty { room: RoomCoord, door: (i8, i8) },
+    /// The room on the other side does not have a door back
+    OneSidedDoor { room: RoomCoord, door: (i8, i8) },
+    EmptyBase { team_id: u64, room: RoomCoord },
+    /// Every team needs a base of its own
+    SharedBase { team_id: u64, room: RoomCoord },
+    /// The spawn point is outside the walkable parts of the level
+    InvalidSpawn { team_id: u64, spawn: SpawnPoint },
+    /// Props have to be inside a full room
+    InvalidProp { prop: PropPlacement },
+    /// The prop is solid and too close to a door for players to get past
+    PropBlocksDoor { prop: PropPlacement },
+    DisconnectedBases { from: RoomCoord, to: RoomCoord },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::WrongColumnLength { col, expected, found } => write!(
+                f, "column {} has {} rooms but should have {}", col, found, expected
+            ),
+            LevelError::InvalidDoor { room, door } => {
+                write!(f, "room {:?} has an invalid door {:?}", room, door)
+            }
+            LevelError::DoorOutOfBounds { room, door } => {
+                write!(f, "door {:?} in room {:?} leads outside the level", door, room)
+            }
+            LevelError::MisalignedDoor { room, door } => write!(
+                f, "door {:?} in room {:?} leads to a room that is not next to it", door, room
+            ),
+            LevelError::DoorIntoEmpty { room, door } => {
+                write!(f, "door {:?} in room {:?} leads to an empty room", door, room)
+            }
+            LevelError::OneSidedDoor { room, door } => write!(
+                f, "door {:?} in room {:?} has no matching door on the other side", door, room
+            ),
+            LevelError::EmptyBase { team_id, room } => {
+                write!(f, "the base {:?} of team {} is empty", room, team_id)
+            }
+            LevelError::SharedBase { team_id, room } => {
+                write!(f, "the base {:?} of team {} belongs to another team", room, team_id)
+            }
+            LevelError::InvalidSpawn { team_id, spawn } => write!(
+                f, "spawn point {:?} in room {:?} of team {} is not inside the level",
+                spawn.offset, spawn.room, team_id
+            ),
+            LevelError::InvalidProp { prop } => write!(
+                f, "{} at {:?} in room {:?} is not inside a full room",
+                prop.kind.name(), prop.offset, prop.room
+            ),
+            LevelError::PropBlocksDoor { prop } => write!(
+                f, "{} at {:?} in room {:?} is in the way of a door",
+                prop.kind.name(), prop.offset, prop.room
+            ),
+            LevelError::DisconnectedBases { from, to } => {
+                write!(f, "there is no path between the bases {:?} and {:?}", from, to)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelLoadError {
+    Io(io::Error),
+    Parse(ron::de::Error),
+    Invalid(Vec<LevelError>),
+}
+
+impl fmt::Display for LevelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelLoadError::Io(e) => write!(f, "could not read level: {}", e),
+            LevelLoadError::Parse(e) => write!(f, "could not parse level: {}", e),
+            LevelLoadError::Invalid(errors) => {
+                write!(f, "invalid level:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Level {
+    /// Reads a level written in RON, see `resources/levels` for examples.
+    /// The level is validated before it is returned
+    pub fn load(path: impl AsRef<Path>) -> Result<Level, LevelLoadError> {
+        let text = fs::read_to_string(path).map_err(LevelLoadError::Io)?;
+        Level::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Level, LevelLoadError> {
+        let level: Level = ron::de::from_str(text).map_err(LevelLoadError::Parse)?;
+        level.validate().map_err(LevelLoadError::Invalid)?;
+        Ok(level)
+    }
+
+    /// Checks that the level is well formed, so that it can be used without
+    /// anything panicking further down the line
+    pub fn validate(&self) -> Result<(), Vec<LevelError>> {
+        let mut errors = vec![];
+
+        for (col, column) in self.rooms.iter().enumerate() {
+            let expected = rooms_in_col(col);
+            if column.len() != expected {
+                errors.push(LevelError::WrongColumnLength {
+                    col,
+                    expected,
+                    found: column.len(),
+                });
+            }
+
+            for (row, room) in column.iter().enumerate() {
+                for &door in room.doors() {
+                    if let Some(error) = self.check_door((col, row), door) {
+                        errors.push(error);
+                    }
+                }
+            }
+        }
+
+        // Doors need to be sound before we can look for paths through them
+        if errors.is_empty() {
+            errors.extend(self.check_bases());
+            errors.extend(self.check_props());
+            errors.extend(self.check_spawns());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check_door(&self, room: RoomCoord, door: (i8, i8)) -> Option<LevelError> {
+        if !is_door_direction(door) {
+            return Some(LevelError::InvalidDoor { room, door });
+        }
+
+        let target = match door_target(room, door) {
+            Some(target) => target,
+            None => return Some(LevelError::DoorOutOfBounds { room, door }),
+        };
+
+        if !is_aligned(room, target) {
+            return Some(LevelError::MisalignedDoor { room, door });
+        }
+
+        let target_room = match self.room(target) {
+            Some(target_room) => target_room,
+            None => return Some(LevelError::DoorOutOfBounds { room, door }),
+        };
+
+        if target_room.is_empty() {
+            return Some(LevelError::DoorIntoEmpty { room, door });
+        }
+
+        let (dx, dy) = door;
+        if !target_room.doors().contains(&(-dx, -dy)) {
+            return Some(LevelError::OneSidedDoor { room, door });
+        }
+
+        None
+    }
+
+    fn check_props(&self) -> Vec<LevelError> {
+        self.props.iter().filter_map(|&prop| self.check_prop(prop)).collect()
+    }
+
+    fn check_prop(&self, prop: PropPlacement) -> Option<LevelError> {
+        let (col, row) = prop.room;
+        let doors = match self.room(prop.room) {
+            Some(FullRoom(doors)) => doors,
+            _ => return Some(LevelError::InvalidProp { prop }),
+        };
+
+        let bounds = prop.bounds();
+        let room = room_bounds(col, row);
+        if !room.contains(bounds.min) || !room.contains(bounds.max) {
+            return Some(LevelError::InvalidProp { prop });
+        }
+
+        // There has to be room for a player to walk around the prop to get
+        // to the door
+        let clearance = bounds.shrunk(vec2(-2. * PLAYER_RADIUS, -2. * PLAYER_RADIUS));
+        let blocks_door = doors.iter().any(|&door| {
+            let (a, b) = doorway_bounds(prop.room, door);
+            Rect::from_corners(a, b).intersects(&clearance)
+        });
+        if prop.kind.is_solid() && blocks_door {
+            return Some(LevelError::PropBlocksDoor { prop });
+        }
+
+        None
+    }
+
+    fn check_spawns(&self) -> Vec<LevelError> {
+        let mut errors = vec![];
+        for (&team_id, spawns) in &self.spawns {
+            for &spawn in spawns {
+                if !collision::is_walkable(self, &[], spawn.position(), PLAYER_RADIUS) {
+                    errors.push(LevelError::InvalidSpawn { team_id, spawn });
+                }
+            }
+        }
+        errors
+    }
+
+    fn check_bases(&self) -> Vec<LevelError> {
+        let bases = self.bases();
+        let mut errors = vec![];
+
+        for (team_id, &room) in bases.iter().enumerate() {
+            let team_id = team_id as u64;
+            if self.room(room).map(Room::is_empty).unwrap_or(true) {
+                errors.push(LevelError::EmptyBase { team_id, room });
+            } else if bases[..team_id as usize].contains(&room) {
+                errors.push(LevelError::SharedBase { team_id, room });
+            }
+        }
+        if !errors.is_empty() {
+            return errors;
+        }
+
+        let graph = RoomGraph::new(self);
+        let from = bases[0];
+        let reachable = graph.reachable_from(from);
+        for &to in &bases[1..] {
+            if !reachable.contains(&to) {
+                errors.push(LevelError::DisconnectedBases { from, to });
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    Room(RoomCoord),
+    /// The passage between two neighbouring rooms
+    Doorway(RoomCoord, RoomCoord),
+}
+
+/// The rooms of a level and how they connect, for answering questions about
+/// where things are and how to get between places.
+#[derive(Clone, Debug)]
+pub struct RoomGraph {
+    rooms: Vec<RoomCoord>,
+    neighbours: HashMap<RoomCoord, Vec<RoomCoord>>,
+    doorways: Vec<(RoomCoord, RoomCoord, Rect)>,
+}
+
+impl RoomGraph {
+    pub fn new(level: &Level) -> Self {
+        let mut rooms = vec![];
+        let mut neighbours = HashMap::new();
+        let mut doorways = vec![];
+
+        for (col, column) in level.rooms.iter().enumerate() {
+            for (row, room) in column.iter().enumerate() {
+                if room.is_empty() {
+                    continue;
+                }
+                let coord = (col, row);
+                rooms.push(coord);
+
+                let mut room_neighbours = vec![];
+                for &door in room.doors() {
+                    if !is_door_direction(door) {
+                        continue;
+                    }
+                    let target = match door_target(coord, door) {
+                        Some(target) => target,
+                        None => continue,
+                    };
+                    room_neighbours.push(target);
+
+                    // Each room covers the half of the doorway closest to it.
+                    // Both halves are stored with the rooms in the same order
+                    let (a, b) = doorway_bounds(coord, door);
+                    let pair = (coord.min(target), coord.max(target));
+                    doorways.push((pair.0, pair.1, Rect::from_corners(a, b)));
+                }
+                neighbours.insert(coord, room_neighbours);
+            }
+        }
+
+        RoomGraph {
+            rooms,
+            neighbours,
+            doorways,
+        }
+    }
+
+    /// All rooms that are not empty
+    pub fn rooms(&self) -> &[RoomCoord] {
+        &self.rooms
+    }
+
+    /// The rooms that the doors of `room` lead to
+    pub fn neighbours(&self, room: RoomCoord) -> &[RoomCoord] {
+        self.neighbours.get(&room).map(|n| &n[..]).unwrap_or(&[])
+    }
+
+    pub fn locate(&self, position: Vec2) -> Option<Location> {
+        let room = self
+            .rooms
+            .iter()
+            .find(|&&(col, row)| room_bounds(col, row).contains(position));
+        if let Some(&room) = room {
+            return Some(Location::Room(room));
+        }
+
+        self.doorways
+            .iter()
+            .find(|(_, _, bounds)| bounds.contains(position))
+            .map(|&(a, b, _)| Location::Doorway(a, b))
+    }
+
+    /// Every room that can be walked to from `start`, including itself
+    pub fn reachable_from(&self, start: RoomCoord) -> HashSet<RoomCoord> {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some(room) = queue.pop_front() {
+            if !visited.insert(room) {
+                continue;
+            }
+            queue.extend(self.neighbours(room));
+        }
+
+        visited
+    }
+
+    /// Rooms that can't be walked to from `start`, which is usually one of
+    /// the team bases
+    pub fn unreachable_rooms(&self, start: RoomCoord) -> Vec<RoomCoord> {
+        let reachable = self.reachable_from(start);
+        self.rooms
+            .iter()
+            .filter(|room| !reachable.contains(room))
+            .cloned()
+            .collect()
+    }
+}
+
 use Room::*;
+/// The level that ships with the game, see `resources/levels/example.ron`
 pub fn example_level() -> Level {
-    Level {
-        rooms: [
-            vec![FullRoom(vec![(1, 0)])],
-            vec![
-                FullRoom(vec![(-1, 0), (0, 1), (1, 0)]),
-                Corridor(vec![(0, -1), (1, 0)]),
-            ],
-            vec![
-                FullRoom(vec![(-1, 0), (1, 1)]),
-                Corridor(vec![(-1, 0), (0, 1)]),
-                FullRoom(vec![(0, -1), (1, 1)]),
-            ],
-            vec![
-                FullRoom(vec![(0, 1), (1, 0)]),
-                Corridor(vec![(-1, -1), (0, -1), (1, 0)]),
-                Corridor(vec![(1, 0), (0, 1)]),
-                Corridor(vec![(-1, -1), (0, -1), (1, 0)]),
-            ],
-            vec![
-                FullRoom(vec![(-1, 0), (0, 1)]),
-                FullRoom(vec![(-1, 0), (0, -1), (1, -1)]),
-                FullRoom(vec![(-1, 0), (1, -1), (0, 1)]),
-                Corridor(vec![(-1, 0), (0, -1)]),
-            ],
-            vec![
-                FullRoom(vec![(-1, 1), (1, 0), (0, 1)]),
-                FullRoom(vec![(-1, 1), (0, -1), (1, -1)]),
-                Empty,
-            ],
-            vec![
-                FullRoom(vec![(-1, 0), (-1, 1), (1, 0)]),
-                Empty,
-            ],
-            vec![FullRoom(vec![(-1, 0)])]
-        ]
-    }
-}
-
-// pub fn generate_level() -> Level {
-//     let mut rng = rand::thread_rng();
-
-//     let mut rooms = [
-//         vec![Room],
-//     ];
-
-//     Level {
-//         rooms
-//     }
-// }
+    Level::parse(include_str!("../../resources/levels/example.ron"))
+        .expect("The bundled example level is broken")
+}
+
+/// Chance for a room other than the bases to be left out
+const EMPTY_ROOM_CHANCE: f64 = 0.2;
+const CORRIDOR_CHANCE: f64 = 0.35;
+/// Chance for each door that isn't needed to keep the level connected
+const EXTRA_DOOR_CHANCE: f64 = 0.3;
+/// Chance for a full room other than the bases to get a prop
+const PROP_CHANCE: f64 = 0.6;
+/// Where generated props go, in the corners of a room
+const PROP_OFFSETS: [(f32, f32); 4] = [(-0.8, -1.7), (0.8, -1.7), (-0.8, 1.7), (0.8, 1.7)];
+const PROP_KINDS: [PropKind; 4] =
+    [PropKind::ServerRack, PropKind::Gpu, PropKind::OilBarrel, PropKind::Oven];
+const MIN_RACKS_PER_TEAM: usize = 2;
+/// The bases of generated levels, which get used in this order: left, right,
+/// top and bottom
+const GENERATED_BASES: [RoomCoord; 4] = [(0, 0), (7, 0), (3, 0), (4, 3)];
+
+/// The most teams that a generated level has bases for
+pub const MAX_GENERATED_TEAMS: usize = GENERATED_BASES.len();
+
+/// The team whose base is closest to the room
+fn side_of(bases: &[RoomCoord], (col, row): RoomCoord) -> u64 {
+    let center = room_center(col, row);
+    let distance = |&(base_col, base_row): &RoomCoord| {
+        (room_center(base_col, base_row) - center).mag()
+    };
+    (0..bases.len())
+        .min_by(|&a, &b| distance(&bases[a]).partial_cmp(&distance(&bases[b])).unwrap())
+        .unwrap_or(0) as u64
+}
+
+impl Level {
+    /// Places a prop unless it would be in the way of a door or overlap
+    /// another prop. Returns whether the prop was placed
+    fn try_add_prop(&mut self, prop: PropPlacement) -> bool {
+        let overlaps = self.props.iter().any(|other| other.bounds().intersects(&prop.bounds()));
+        let fits = !overlaps && self.check_prop(prop).is_none();
+        if fits {
+            self.props.push(prop);
+        }
+        fits
+    }
+}
+
+/// Every pair of neighbouring rooms that could have a door between them,
+/// along with the door offset from the first room
+fn possible_doors() -> Vec<(RoomCoord, RoomCoord, (i8, i8))> {
+    let mut doors = vec![];
+    for col in 0..COLUMN_COUNT {
+        for row in 0..rooms_in_col(col) {
+            for &dx in &[0, 1] {
+                for &dy in &[-1, 0, 1] {
+                    let door = (dx, dy);
+                    if !is_door_direction(door) || door == (0, -1) {
+                        continue;
+                    }
+                    match door_target((col, row), door) {
+                        Some(target) if is_aligned((col, row), target) => {
+                            doors.push(((col, row), target, door));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    doors
+}
+
+/// Generates a random level with bases for `team_count` teams, where all the
+/// rooms are connected, and in particular there is always a path between the
+/// team bases. The same seed always gives the same level.
+pub fn generate_level(seed: u64, team_count: usize) -> Level {
+    assert!(
+        team_count >= 1 && team_count <= MAX_GENERATED_TEAMS,
+        "Generated levels have bases for 1 to {} teams",
+        MAX_GENERATED_TEAMS
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let possible_doors = possible_doors();
+    let bases = &GENERATED_BASES[..team_count];
+
+    loop {
+        let mut used: HashSet<RoomCoord> = HashSet::new();
+        for col in 0..COLUMN_COUNT {
+            for row in 0..rooms_in_col(col) {
+                if bases.contains(&(col, row)) || !rng.gen_bool(EMPTY_ROOM_CHANCE) {
+                    used.insert((col, row));
+                }
+            }
+        }
+
+        let candidates: Vec<_> = possible_doors
+            .iter()
+            .filter(|(a, b, _)| used.contains(a) && used.contains(b))
+            .cloned()
+            .collect();
+
+        // Grow a random spanning tree outwards from the first base
+        let mut connected = HashSet::new();
+        connected.insert(bases[0]);
+        let mut doors = vec![];
+        loop {
+            let frontier: Vec<_> = candidates
+                .iter()
+                .filter(|(a, b, _)| connected.contains(a) != connected.contains(b))
+                .collect();
+            match frontier.choose(&mut rng) {
+                Some(&&(a, b, door)) => {
+                    connected.insert(a);
+                    connected.insert(b);
+                    doors.push((a, b, door));
+                }
+                None => break,
+            }
+        }
+
+        if !bases.iter().all(|base| connected.contains(base)) {
+            continue;
+        }
+
+        for &(a, b, door) in &candidates {
+            let is_new = !doors.iter().any(|&(c, d, _)| (c, d) == (a, b));
+            if is_new && connected.contains(&a) && rng.gen_bool(EXTRA_DOOR_CHANCE) {
+                doors.push((a, b, door));
+            }
+        }
+
+        let mut rooms: [Vec<Room>; COLUMN_COUNT] = Default::default();
+        for col in 0..COLUMN_COUNT {
+            for row in 0..rooms_in_col(col) {
+                let coord = (col, row);
+                if !connected.contains(&coord) {
+                    rooms[col].push(Empty);
+                    continue;
+                }
+
+                let mut room_doors = vec![];
+                for &(a, b, (dx, dy)) in &doors {
+                    if a == coord {
+                        room_doors.push((dx, dy));
+                    } else if b == coord {
+                        room_doors.push((-dx, -dy));
+                    }
+                }
+
+                if !bases.contains(&coord) && rng.gen_bool(CORRIDOR_CHANCE) {
+                    rooms[col].push(Corridor(room_doors));
+                } else {
+                    rooms[col].push(FullRoom(room_doors));
+                }
+            }
+        }
+
+        let mut level = Level {
+            rooms,
+            bases: bases.to_vec(),
+            spawns: HashMap::new(),
+            props: vec![],
+            collision_areas: Default::default(),
+        };
+
+        let prop_rooms: Vec<RoomCoord> = (0..COLUMN_COUNT)
+            .flat_map(|col| (0..rooms_in_col(col)).map(move |row| (col, row)))
+            .filter(|&(col, row)| match level.rooms[col][row] {
+                FullRoom(_) => !bases.contains(&(col, row)),
+                _ => false,
+            })
+            .collect();
+
+        // Server racks belong to the team whose side they are on
+        for &room in &prop_rooms {
+            if rng.gen_bool(PROP_CHANCE) {
+                let kind = *PROP_KINDS.choose(&mut rng).unwrap();
+                let team = match kind {
+                    PropKind::ServerRack => Some(side_of(bases, room)),
+                    _ => None,
+                };
+                let offset = *PROP_OFFSETS.choose(&mut rng).unwrap();
+                level.try_add_prop(PropPlacement { kind, room, offset, team });
+            }
+        }
+
+        // Every team needs racks of its own for the others to hack. With
+        // many teams, a side might not have enough rooms for them, in which
+        // case they go in the closest rooms outside of it
+        for team_id in 0..team_count as u64 {
+            let (base_col, base_row) = bases[team_id as usize];
+            let distance = |&(col, row): &RoomCoord| {
+                (room_center(col, row) - room_center(base_col, base_row)).mag()
+            };
+            let (mut rooms, mut other_rooms): (Vec<_>, Vec<_>) = prop_rooms.iter()
+                .partition(|&&room| side_of(bases, room) == team_id);
+            rooms.shuffle(&mut rng);
+            other_rooms.sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+            rooms.extend(other_rooms);
+
+            let mut racks = level.props.iter()
+                .filter(|prop| prop.kind == PropKind::ServerRack && prop.team == Some(team_id))
+                .count();
+            let spots = rooms.into_iter()
+                .flat_map(|room| PROP_OFFSETS.iter().map(move |&offset| (room, offset)));
+            for (room, offset) in spots {
+                if racks >= MIN_RACKS_PER_TEAM {
+                    break;
+                }
+                let kind = PropKind::ServerRack;
+                if level.try_add_prop(PropPlacement { kind, room, offset, team: Some(team_id) }) {
+                    racks += 1;
+                }
+            }
+        }
+
+        return level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(level: &Level) -> Vec<LevelError> {
+        level.validate().err().unwrap_or_default()
+    }
+
+    #[test]
+    fn example_level_is_valid() {
+        assert_eq!(example_level().validate(), Ok(()));
+    }
+
+    #[test]
+    fn generated_levels_are_valid() {
+        for team_count in 1..=MAX_GENERATED_TEAMS {
+            for seed in 0..100 {
+                let level = generate_level(seed, team_count);
+                assert_eq!(level.bases().len(), team_count);
+                assert_eq!(
+                    level.validate(),
+                    Ok(()),
+                    "seed {} with {} teams",
+                    seed,
+                    team_count,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn doors_need_a_door_back() {
+        let mut level = example_level();
+        level.rooms[7][0] = FullRoom(vec![]);
+        assert_eq!(
+            errors(&level),
+            vec![LevelError::OneSidedDoor { room: (6, 0), door: (1, 0) }],
+        );
+    }
+
+    #[test]
+    fn doors_stay_inside_the_grid() {
+        let mut level = example_level();
+        level.rooms[0][0] = FullRoom(vec![(1, 0), (-1, 0)]);
+        assert_eq!(
+            errors(&level),
+            vec![LevelError::DoorOutOfBounds { room: (0, 0), door: (-1, 0) }],
+        );
+    }
+
+    #[test]
+    fn columns_have_the_right_length() {
+        let mut level = example_level();
+        level.rooms[2].pop();
+        let errors = errors(&level);
+        assert!(errors.contains(&LevelError::WrongColumnLength { col: 2, expected: 3, found: 2 }));
+    }
+
+    #[test]
+    fn bases_are_connected() {
+        let mut level = example_level();
+        level.rooms[6][0] = FullRoom(vec![(-1, 0), (-1, 1)]);
+        level.rooms[7][0] = FullRoom(vec![]);
+        assert_eq!(
+            errors(&level),
+            vec![LevelError::DisconnectedBases { from: (0, 0), to: (7, 0) }],
+        );
+    }
+}
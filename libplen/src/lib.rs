@@ -2,6 +2,14 @@ pub mod player;
 pub mod constants;
 pub mod math;
 pub mod gamestate;
+pub mod gamemode;
 pub mod messages;
 pub mod debug;
 pub mod level;
+pub mod flag;
+pub mod collision;
+pub mod marker;
+pub mod weapon;
+pub mod killfeed;
+pub mod door;
+pub mod prop;
@@ -0,0 +1,81 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::constants::{MARKER_DURATION, MAX_MARKERS};
+use crate::math::Vec2;
+
+/// The kinds of markers that a dispatcher can place for their team
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum MarkerKind {
+    Waypoint,
+    Danger,
+    /// An order to move somewhere. There is only ever one of these
+    GoHere,
+}
+
+impl MarkerKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarkerKind::Waypoint => "Waypoint",
+            MarkerKind::Danger => "Danger",
+            MarkerKind::GoHere => "Go here",
+        }
+    }
+
+    /// Matches the colour of the marker sprites in `resources/markers`
+    pub fn color(&self) -> (u8, u8, u8) {
+        match self {
+            MarkerKind::Waypoint => (255, 210, 0),
+            MarkerKind::Danger => (255, 70, 0),
+            MarkerKind::GoHere => (0, 220, 80),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    pub position: Vec2,
+    pub time_left: f32,
+}
+
+impl Marker {
+    pub fn new(kind: MarkerKind, position: Vec2) -> Marker {
+        Marker {
+            kind,
+            position,
+            time_left: MARKER_DURATION,
+        }
+    }
+}
+
+/// The markers that a client currently shows, oldest first
+pub struct MarkerList {
+    markers: Vec<Marker>,
+}
+
+impl MarkerList {
+    pub fn new() -> MarkerList {
+        MarkerList { markers: vec![] }
+    }
+
+    pub fn add(&mut self, marker: Marker) {
+        if marker.kind == MarkerKind::GoHere {
+            self.markers.retain(|m| m.kind != MarkerKind::GoHere);
+        }
+        self.markers.push(marker);
+        if self.markers.len() > MAX_MARKERS {
+            self.markers.remove(0);
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        for marker in &mut self.markers {
+            marker.time_left -= delta_time;
+        }
+        self.markers.retain(|marker| marker.time_left > 0.);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Marker> {
+        self.markers.iter()
+    }
+}
@@ -31,3 +31,93 @@ pub fn angle_diff(source_angle: f32, target_angle: f32) -> f32 {
     use std::f32::consts::PI;
     modulo(target_angle - source_angle + PI, 2. * PI) - PI
 }
+
+/// Axis aligned rectangle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Rect {
+    pub fn from_corners(a: Vec2, b: Vec2) -> Self {
+        Rect {
+            min: a.min_by_component(b),
+            max: a.max_by_component(b),
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vec2 {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.x >= self.min.x && point.x <= self.max.x
+            && point.y >= self.min.y && point.y <= self.max.y
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x
+            && self.min.y <= other.max.y && other.min.y <= self.max.y
+    }
+
+    /// How far `point` is from the rect, 0 if it is inside
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        let closest = point.max_by_component(self.min).min_by_component(self.max);
+        (point - closest).mag()
+    }
+
+    /// How far `point` is from the closest side, 0 if it is outside
+    pub fn depth(&self, point: Vec2) -> f32 {
+        if !self.contains(point) {
+            return 0.;
+        }
+        (point.x - self.min.x)
+            .min(self.max.x - point.x)
+            .min(point.y - self.min.y)
+            .min(self.max.y - point.y)
+    }
+
+    /// Moves every side inwards by `amount`. Negative values grow the rect
+    pub fn shrunk(&self, amount: Vec2) -> Self {
+        Rect {
+            min: self.min + amount,
+            max: self.max - amount,
+        }
+    }
+
+    /// The part of the line segment from `from` to `to` that lies inside
+    /// the rect, as fractions of the way along the segment
+    pub fn clip_segment(&self, from: Vec2, to: Vec2) -> Option<(f32, f32)> {
+        let delta = to - from;
+        let mut enter = 0f32;
+        let mut exit = 1f32;
+
+        let axes = [
+            (from.x, delta.x, self.min.x, self.max.x),
+            (from.y, delta.y, self.min.y, self.max.y),
+        ];
+        for &(start, delta, min, max) in &axes {
+            if delta == 0. {
+                if start < min || start > max {
+                    return None;
+                }
+            } else {
+                let a = (min - start) / delta;
+                let b = (max - start) / delta;
+                enter = enter.max(a.min(b));
+                exit = exit.min(a.max(b));
+            }
+        }
+
+        if enter <= exit {
+            Some((enter, exit))
+        } else {
+            None
+        }
+    }
+}
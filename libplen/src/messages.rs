@@ -4,9 +4,11 @@ use std::collections::VecDeque;
 use std::iter::Iterator;
 
 use serde_derive::{Serialize, Deserialize};
+use enum_map::Enum;
 
 use crate::player;
 use crate::math::Vec2;
+use crate::weapon::WeaponKind;
 
 pub struct MessageReader {
     pub stream: TcpStream,
@@ -74,7 +76,7 @@ impl Iterator for MessageIterator<'_> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Enum)]
 pub enum SoundEffect { Powerup, Explosion, Gun, LaserCharge, LaserFire }
 
 #[derive(Serialize, Deserialize)]
@@ -82,13 +84,24 @@ pub enum ServerMessage {
     AssignId(u64),
     GameState(crate::gamestate::GameState),
     PlaySound(SoundEffect, Vec2),
+    /// The level that the next match is played on
+    Level(crate::level::Level),
+    /// A marker placed by the dispatcher of the receiving team
+    Marker(crate::marker::Marker),
+    /// The last `ClientMessage::JoinTeam` was turned down
+    JoinRejected(player::JoinError),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ClientInput {
     pub rotation: f32,
     pub x_input: f32,
     pub y_input: f32,
+    /// Whether the trigger is held down
+    pub fire: bool,
+    pub weapon: WeaponKind,
+    /// Whether the interaction key is held down
+    pub interact: bool,
 }
 
 impl ClientInput {
@@ -97,13 +110,32 @@ impl ClientInput {
             rotation: 0.,
             x_input: 0.,
             y_input: 0.,
+            fire: false,
+            weapon: WeaponKind::Gun,
+            interact: false,
         }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientMessage {
-    JoinTeam { team_id: u64, player_type: player::PlayerType, name: String },
+    /// See `GameState::join_team`. Without a `team_id`, the server picks the
+    /// team with the fewest players
+    JoinTeam {
+        team_id: Option<u64>,
+        player_type: player::PlayerType,
+        name: String,
+        queue: bool,
+    },
     Input(ClientInput),
     SetName { name: String },
+    SetReady(bool),
+    /// Only accepted from dispatchers, the marker is shown to their team
+    PlaceMarker { kind: crate::marker::MarkerKind, position: Vec2 },
+    /// Only accepted from dispatchers, see `GameState::set_door`
+    SetDoor {
+        room: crate::level::RoomCoord,
+        direction: (i8, i8),
+        state: crate::door::DoorState,
+    },
 }
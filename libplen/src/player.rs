@@ -1,10 +1,14 @@
 use serde_derive::{Serialize, Deserialize};
-use crate::constants::PLAYER_SPEED;
+use crate::collision;
+use crate::constants::{PLAYER_MAX_HEALTH, PLAYER_RADIUS, PLAYER_SPEED};
+use crate::door::Door;
+use crate::level::Level;
 use crate::math::{Vec2, vec2};
 use crate::messages::ClientInput;
+use crate::weapon::Weapon;
 use ultraviolet::Rotor2;
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum PlayerType {
     Dispatcher,
     Agent
@@ -17,6 +21,14 @@ pub struct Player {
     pub position: Vec2,
     pub rotation: f32,
     pub player_type: PlayerType,
+    pub ready: bool,
+    /// False in snapshots sent to clients whose team cannot see this
+    /// player. The position and rotation are meaningless in that case
+    pub visible: bool,
+    pub health: f32,
+    /// Seconds until a dead player respawns
+    pub respawn_time: f32,
+    pub weapon: Weapon,
 }
 
 
@@ -32,19 +44,72 @@ impl Player {
             position: vec2(0., 0.),
             rotation: 0.,
             player_type,
+            ready: false,
+            visible: true,
+            health: PLAYER_MAX_HEALTH,
+            respawn_time: 0.,
+            weapon: Weapon::new(),
         }
     }
 
-    pub fn update(&mut self, delta_time: f32, input: &ClientInput) {
+    pub fn is_alive(&self) -> bool {
+        self.health > 0.
+    }
+
+    /// Brings the player back to full health at `position`
+    pub fn respawn(&mut self, position: Vec2) {
+        self.position = position;
+        self.health = PLAYER_MAX_HEALTH;
+        self.respawn_time = 0.;
+        self.weapon = Weapon::new();
+    }
+
+    /// The direction the player is looking in
+    pub fn facing(&self) -> Vec2 {
+        vec2(0., -1.).rotated_by(Rotor2::from_angle(-self.rotation))
+    }
+
+    pub fn update(&mut self, delta_time: f32, input: &ClientInput, level: &Level, doors: &[Door]) {
+        if !self.is_alive() {
+            return;
+        }
         let &ClientInput {
             rotation,
             x_input,
             y_input,
+            ..
         } = input;
         self.rotation -= rotation; // No delta time factor here!
         let input_movement = vec2(x_input, y_input)
             .rotated_by(Rotor2::from_angle(-self.rotation));
-        self.position += input_movement * PLAYER_SPEED * delta_time;
+        self.position = collision::move_with_collision(
+            level,
+            doors,
+            self.position,
+            input_movement * PLAYER_SPEED * delta_time,
+            PLAYER_RADIUS,
+        );
+    }
+}
+
+/// Why the server turned down a request to join a team
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum JoinError {
+    NoSuchTeam,
+    /// The team already has a dispatcher, and the player didn't want to
+    /// queue for the role
+    DispatcherTaken,
+    /// Every team already has a dispatcher
+    NoDispatcherSlots,
+}
+
+impl JoinError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            JoinError::NoSuchTeam => "there is no such team",
+            JoinError::DispatcherTaken => "the team already has a dispatcher",
+            JoinError::NoDispatcherSlots => "every team already has a dispatcher",
+        }
     }
 }
 
@@ -55,6 +120,10 @@ pub struct Team {
     pub color: (u8, u8, u8),
     pub dispatcher: Option<Player>,
     pub agents: Vec<Player>,
+    /// Ids of the agents waiting for the dispatcher to leave so that they can
+    /// take over, first in line first
+    pub dispatcher_queue: Vec<u64>,
+    pub score: u32,
 }
 
 impl Team {
@@ -64,24 +133,90 @@ impl Team {
             name,
             color,
             dispatcher: None,
-            agents: vec!()
+            agents: vec!(),
+            dispatcher_queue: vec![],
+            score: 0,
         }
     }
 
-    pub fn try_add_player(&mut self, player_id: u64, name: String, player_type: PlayerType) {
+    pub fn try_add_player(
+        &mut self,
+        player_id: u64,
+        name: String,
+        player_type: PlayerType,
+    ) -> Result<(), JoinError> {
         match player_type {
             PlayerType::Dispatcher => {
                 match self.dispatcher {
                     None => {
                         self.dispatcher = Some(Player::new(player_id, name, player_type));
                     },
-                    Some(_) => { }, // don't add dispatcher if already taken
+                    Some(_) => return Err(JoinError::DispatcherTaken),
                 }
             },
             PlayerType::Agent => {
                 self.agents.push(Player::new(player_id, name, player_type));
             }
         }
+        Ok(())
+    }
+
+    pub fn is_dispatcher(&self, player_id: u64) -> bool {
+        self.dispatcher.as_ref().map(|d| d.id == player_id).unwrap_or(false)
+    }
+
+    /// Whether someone other than the player is the dispatcher of the team
+    pub fn has_other_dispatcher(&self, player_id: u64) -> bool {
+        self.dispatcher.as_ref().map(|d| d.id != player_id).unwrap_or(false)
+    }
+
+    pub fn is_agent(&self, player_id: u64) -> bool {
+        self.agents.iter().any(|p| p.id == player_id)
+    }
+
+    /// Puts one of the agents of the team at the back of the line for the
+    /// dispatcher role
+    pub fn queue_dispatcher(&mut self, player_id: u64) {
+        if self.is_agent(player_id) && !self.dispatcher_queue.contains(&player_id) {
+            self.dispatcher_queue.push(player_id);
+        }
+    }
+
+    /// How many players are ahead of the player in line for the dispatcher
+    /// role, if they are in line
+    pub fn queue_position(&self, player_id: u64) -> Option<usize> {
+        self.dispatcher_queue.iter().position(|&id| id == player_id)
+    }
+
+    /// Fills an empty dispatcher slot with the first agent in line for it.
+    /// With `allow_agents`, one of the other agents takes over if nobody is
+    /// waiting, as long as the team keeps an agent. Returns the id of the
+    /// new dispatcher
+    pub fn hand_off_dispatcher(&mut self, allow_agents: bool) -> Option<u64> {
+        if self.dispatcher.is_some() {
+            return None;
+        }
+        let index = if !self.dispatcher_queue.is_empty() {
+            let id = self.dispatcher_queue.remove(0);
+            self.agents.iter().position(|p| p.id == id)
+        } else if allow_agents && self.agents.len() > 1 {
+            Some(0)
+        } else {
+            None
+        };
+        let mut dispatcher = self.agents.remove(index?);
+        dispatcher.player_type = PlayerType::Dispatcher;
+        let id = dispatcher.id;
+        self.dispatcher = Some(dispatcher);
+        Some(id)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.dispatcher.iter().chain(self.agents.iter())
+    }
+
+    pub fn players_mut(&mut self) -> impl Iterator<Item = &mut Player> {
+        self.dispatcher.iter_mut().chain(self.agents.iter_mut())
     }
 
     pub fn has_player(&self, id: u64) -> bool {
@@ -112,5 +247,6 @@ impl Team {
             }
         };
         self.agents.retain(|p| p.id != id);
+        self.dispatcher_queue.retain(|&queued| queued != id);
     }
 }
@@ -0,0 +1,138 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::constants::BARREL_HEALTH;
+use crate::level::{self, RoomCoord};
+use crate::math::{Rect, Vec2, vec2};
+
+/// The objects that can be placed in rooms, drawn with the matching art in
+/// `resources`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PropKind {
+    ServerRack,
+    /// Can be carried around, agents carrying one hack faster
+    Gpu,
+    /// Blows up when shot, hurting everyone around it
+    OilBarrel,
+    /// Just gets in the way. Older levels still call it `Owen`
+    #[serde(alias = "Owen")]
+    Oven,
+}
+
+impl PropKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropKind::ServerRack => "Server rack",
+            PropKind::Gpu => "GPU",
+            PropKind::OilBarrel => "Oil barrel",
+            PropKind::Oven => "Oven",
+        }
+    }
+
+    /// Whether the prop blocks movement
+    pub fn is_solid(&self) -> bool {
+        match self {
+            PropKind::ServerRack | PropKind::Oven => true,
+            PropKind::Gpu | PropKind::OilBarrel => false,
+        }
+    }
+
+    /// Half the width of the prop, which is square when seen from above
+    pub fn radius(&self) -> f32 {
+        match self {
+            PropKind::ServerRack => 0.4,
+            PropKind::Gpu => 0.15,
+            PropKind::OilBarrel => 0.25,
+            PropKind::Oven => 0.35,
+        }
+    }
+
+    /// None for props that can't be destroyed
+    pub fn max_health(&self) -> Option<f32> {
+        match self {
+            PropKind::OilBarrel => Some(BARREL_HEALTH),
+            _ => None,
+        }
+    }
+}
+
+/// A prop in a level, relative to the centre of a room
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PropPlacement {
+    pub kind: PropKind,
+    pub room: RoomCoord,
+    pub offset: (f32, f32),
+    /// The team that the prop belongs to. Server racks of other teams can
+    /// be hacked
+    #[serde(default)]
+    pub team: Option<u64>,
+}
+
+impl PropPlacement {
+    pub fn position(&self) -> Vec2 {
+        let (col, row) = self.room;
+        level::room_center(col, row) + vec2(self.offset.0, self.offset.1)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        prop_bounds(self.kind, self.position())
+    }
+}
+
+/// A prop during a match
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Prop {
+    pub kind: PropKind,
+    pub home: Vec2,
+    pub position: Vec2,
+    pub carrier: Option<u64>,
+    pub health: Option<f32>,
+    pub team: Option<u64>,
+    /// How far along hacking the prop is, from 0 to 1
+    pub hack_progress: f32,
+    /// The team that `hack_progress` belongs to. When another team starts
+    /// hacking the prop, they start over from scratch
+    pub hacking_team: Option<u64>,
+    /// The team that finished hacking the prop
+    pub hacked_by: Option<u64>,
+}
+
+impl Prop {
+    pub fn new(placement: &PropPlacement) -> Prop {
+        Prop {
+            kind: placement.kind,
+            home: placement.position(),
+            position: placement.position(),
+            carrier: None,
+            health: placement.kind.max_health(),
+            team: placement.team,
+            hack_progress: 0.,
+            hacking_team: None,
+            hacked_by: None,
+        }
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.health.map(|health| health <= 0.).unwrap_or(false)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        prop_bounds(self.kind, self.position)
+    }
+
+    /// Whether agents of `team_id` can hack the prop
+    pub fn is_hackable_by(&self, team_id: u64) -> bool {
+        self.kind == PropKind::ServerRack
+            && self.hacked_by.is_none()
+            && self.team.map(|team| team != team_id).unwrap_or(false)
+    }
+
+    pub fn return_home(&mut self) {
+        self.carrier = None;
+        self.position = self.home;
+    }
+}
+
+fn prop_bounds(kind: PropKind, position: Vec2) -> Rect {
+    let radius = kind.radius();
+    Rect::from_corners(position - vec2(radius, radius), position + vec2(radius, radius))
+}
@@ -0,0 +1,136 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::constants::{
+    GUN_COOLDOWN, GUN_DAMAGE, LASER_CHARGE_TIME, LASER_COOLDOWN, LASER_DAMAGE,
+};
+use crate::math::Vec2;
+use crate::messages::SoundEffect;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WeaponKind {
+    /// Hits the first player in the line of fire, as often as the cooldown
+    /// allows
+    Gun,
+    /// Has to be charged before it fires, but hits everyone along the beam
+    Laser,
+}
+
+impl WeaponKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            WeaponKind::Gun => "Gun",
+            WeaponKind::Laser => "Laser",
+        }
+    }
+
+    pub fn damage(self) -> f32 {
+        match self {
+            WeaponKind::Gun => GUN_DAMAGE,
+            WeaponKind::Laser => LASER_DAMAGE,
+        }
+    }
+
+    fn cooldown(self) -> f32 {
+        match self {
+            WeaponKind::Gun => GUN_COOLDOWN,
+            WeaponKind::Laser => LASER_COOLDOWN,
+        }
+    }
+
+    pub fn sound(self) -> SoundEffect {
+        match self {
+            WeaponKind::Gun => SoundEffect::Gun,
+            WeaponKind::Laser => SoundEffect::LaserFire,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WeaponEvent {
+    ChargeStarted,
+    Fired,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    /// Seconds until the weapon can be used again
+    pub cooldown: f32,
+    /// How long the trigger has been held while a laser is charging
+    pub charge: Option<f32>,
+}
+
+impl Weapon {
+    pub fn new() -> Weapon {
+        Weapon {
+            kind: WeaponKind::Gun,
+            cooldown: 0.,
+            charge: None,
+        }
+    }
+
+    /// Switching weapons loses any charge
+    pub fn select(&mut self, kind: WeaponKind) {
+        if kind != self.kind {
+            self.kind = kind;
+            self.charge = None;
+        }
+    }
+
+    /// Advances the cooldown and charge while the trigger is held or not.
+    /// Letting go of the trigger before the laser is charged cancels the shot
+    pub fn update(&mut self, delta: f32, trigger: bool) -> Option<WeaponEvent> {
+        self.cooldown = (self.cooldown - delta).max(0.);
+        if !trigger {
+            self.charge = None;
+            return None;
+        }
+        if self.cooldown > 0. {
+            return None;
+        }
+
+        let fired = match (self.kind, self.charge) {
+            (WeaponKind::Gun, _) => true,
+            (WeaponKind::Laser, None) => {
+                self.charge = Some(0.);
+                return Some(WeaponEvent::ChargeStarted);
+            }
+            (WeaponKind::Laser, Some(charge)) => {
+                self.charge = Some(charge + delta);
+                charge + delta >= LASER_CHARGE_TIME
+            }
+        };
+
+        if fired {
+            self.charge = None;
+            self.cooldown = self.kind.cooldown();
+            Some(WeaponEvent::Fired)
+        } else {
+            None
+        }
+    }
+
+    /// How far along the laser is with charging, from 0 to 1
+    pub fn charge_fraction(&self) -> Option<f32> {
+        self.charge.map(|charge| (charge / LASER_CHARGE_TIME).min(1.))
+    }
+}
+
+/// A recently fired shot, kept around for a moment so clients can draw it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Shot {
+    pub shooter: u64,
+    pub kind: WeaponKind,
+    pub from: Vec2,
+    /// Where the shot hit a wall or, for the gun, a player
+    pub to: Vec2,
+    pub time_left: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Hit {
+    pub shooter: u64,
+    pub target: u64,
+    pub kind: WeaponKind,
+    pub damage: f32,
+}
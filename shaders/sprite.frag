@@ -1,8 +1,18 @@
 in vec2 uv;
 uniform sampler2D tex;
+// The alpha channel controls how strongly the tint is applied
+uniform vec4 tint;
 
 out vec4 frag;
 
 void main() {
-  frag = texture(tex, uv);
+  vec4 color = texture(tex, uv);
+  if (color.a < 0.5) {
+    discard;
+  }
+
+  // Recolor the saturated parts of the sprite, leaving greys untouched
+  float brightness = max(color.r, max(color.g, color.b));
+  float saturation = brightness - min(color.r, min(color.g, color.b));
+  frag = vec4(mix(color.rgb, tint.rgb * brightness, saturation * tint.a), color.a);
 }
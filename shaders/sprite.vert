@@ -1,3 +1,4 @@
+uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
 
@@ -8,6 +9,6 @@ void main() {
   int x = (gl_VertexID + y) % 2;
 
   vec2 pos = vec2(x, y);
-  gl_Position = projection * view * vec4(pos - 0.5, 0., 1.);
+  gl_Position = projection * view * model * vec4(pos - 0.5, 0., 1.);
   uv = vec2(x, 1 - y);
 }
@@ -1,10 +1,98 @@
 in vec2 v_uv;
+in vec3 v_position;
+in vec3 v_normal;
 
 uniform sampler2D albedo;
+uniform sampler2D displacement;
+uniform sampler2D roughness;
+uniform sampler2D ambient_occlusion;
+uniform vec3 tint;
+
+uniform vec3 camera_position;
+uniform vec3 light_position;
+uniform vec3 light_color;
 
 out vec4 frag;
 
+const float PI = 3.14159265;
+const float AMBIENT = 0.15;
+// How far the bumps of the displacement map stick out
+const float BUMP_STRENGTH = 4.0;
+// Reflectance of non-metals when looking straight at them
+const vec3 F0 = vec3(0.04);
+
+// Builds a tangent frame from the screen space derivatives of the position
+// and uv so the vertices don't have to carry tangents
+mat3 tangent_frame(vec3 normal) {
+  vec3 dp1 = dFdx(v_position);
+  vec3 dp2 = dFdy(v_position);
+  vec2 duv1 = dFdx(v_uv);
+  vec2 duv2 = dFdy(v_uv);
+
+  vec3 dp2perp = cross(dp2, normal);
+  vec3 dp1perp = cross(normal, dp1);
+  vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+  vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+  float scale = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+  return mat3(tangent * scale, bitangent * scale, normal);
+}
+
+// There is no normal map, instead the normal is tilted by the slope of the
+// displacement map, found by sampling the neighbouring texels
+vec3 bumped_normal() {
+  vec2 texel = 1.0 / vec2(textureSize(displacement, 0));
+  float left = texture(displacement, v_uv - vec2(texel.x, 0)).r;
+  float right = texture(displacement, v_uv + vec2(texel.x, 0)).r;
+  float down = texture(displacement, v_uv - vec2(0, texel.y)).r;
+  float up = texture(displacement, v_uv + vec2(0, texel.y)).r;
+
+  vec3 slope = vec3(left - right, down - up, 0) * BUMP_STRENGTH;
+  vec3 tangent_normal = normalize(slope + vec3(0, 0, 1));
+  vec3 normal = normalize(v_normal);
+  return normalize(tangent_frame(normal) * tangent_normal);
+}
+
+// GGX normal distribution, Smith geometry and Schlick fresnel
+vec3 specular(vec3 n, vec3 v, vec3 l, float rough) {
+  vec3 h = normalize(v + l);
+  float a = rough * rough;
+  float a2 = a * a;
+  float n_dot_h = max(dot(n, h), 0);
+  float n_dot_v = max(dot(n, v), 0.001);
+  float n_dot_l = max(dot(n, l), 0.001);
+
+  float denominator = n_dot_h * n_dot_h * (a2 - 1) + 1;
+  float distribution = a2 / (PI * denominator * denominator);
+
+  float k = (rough + 1) * (rough + 1) / 8;
+  float geometry = n_dot_v / (n_dot_v * (1 - k) + k) * n_dot_l / (n_dot_l * (1 - k) + k);
+
+  vec3 fresnel = F0 + (1 - F0) * pow(1 - max(dot(h, v), 0), 5);
+
+  return distribution * geometry * fresnel / (4 * n_dot_v * n_dot_l);
+}
+
 void main() {
-  frag.rgb += texture(albedo, v_uv).rgb;
-  frag.a = 1;
+  // The textures are in sRGB, lighting has to happen in linear space
+  vec3 base_color = pow(texture(albedo, v_uv).rgb, vec3(2.2)) * tint;
+  float rough = max(texture(roughness, v_uv).r, 0.05);
+  float occlusion = texture(ambient_occlusion, v_uv).r;
+
+  vec3 n = bumped_normal();
+  vec3 v = normalize(camera_position - v_position);
+  vec3 to_light = light_position - v_position;
+  vec3 l = normalize(to_light);
+
+  float attenuation = 1.0 / (1.0 + dot(to_light, to_light));
+  vec3 radiance = light_color * attenuation;
+
+  vec3 spec = specular(n, v, l, rough);
+  vec3 diffuse = (1 - F0) * base_color / PI;
+  vec3 direct = (diffuse + spec) * radiance * max(dot(n, l), 0);
+
+  vec3 color = AMBIENT * base_color * occlusion + direct * mix(1.0, occlusion, 0.5);
+  // Simple tone mapping so bright spots near the lights don't clip
+  color = color / (color + 1);
+  frag = vec4(pow(color, vec3(1 / 2.2)), 1);
 }
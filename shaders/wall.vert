@@ -1,13 +1,20 @@
 in vec3 position;
 in vec2 uv;
+in vec3 normal;
 
 uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
 
 out vec2 v_uv;
+out vec3 v_position;
+out vec3 v_normal;
 
 void main() {
-  gl_Position = projection * view * model * vec4(position, 1.0);
+  vec4 world_position = model * vec4(position, 1.0);
+  gl_Position = projection * view * world_position;
   v_uv = uv;
+  v_position = world_position.xyz;
+  // Rooms are only ever moved and rotated, so this is enough for normals
+  v_normal = mat3(model) * normal;
 }
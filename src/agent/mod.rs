@@ -15,15 +15,24 @@ use luminance::pipeline::PipelineState;
 use luminance::render_state::RenderState;
 use luminance::shader::BuiltProgram;
 use luminance_derive::{Semantics, Vertex};
-use luminance_glyph::{GlyphBrushBuilder, Section, Text};
+use luminance_glyph::{GlyphBrushBuilder, HorizontalAlign, Layout, Section, Text, VerticalAlign};
 
-use ultraviolet::{Mat4, Vec2, Vec3};
+use ultraviolet::{Mat4, Vec2, Vec3, Vec4};
 
-use libplen::level::{self, Level};
+use libplen::collision;
+use libplen::constants::HACK_RADIUS;
+use libplen::gamemode::Mode;
+use libplen::gamestate::GameState;
+use libplen::level::Level;
+use libplen::marker::{Marker, MarkerKind, MarkerList};
 use libplen::messages::{ClientInput, ClientMessage, MessageReader, ServerMessage, SoundEffect};
 use libplen::player;
+use libplen::prop::PropKind;
+use libplen::weapon::WeaponKind;
 
-use crate::{assets::SoundAssets, constants, gamestate, map, surface, StateResult};
+use crate::assets::{self, SoundAssets};
+use crate::audio::{self, Listener};
+use crate::{constants, gamestate, map, surface, StateResult};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Semantics)]
 pub enum Semantics {
@@ -47,15 +56,19 @@ struct AgentState {
     game_state: gamestate::GameState,
     map: map::Map,
     last_time: Instant,
+    markers: MarkerList,
+    weapon: WeaponKind,
 }
 
 impl AgentState {
-    fn new(my_id: u64) -> AgentState {
+    fn new(my_id: u64, level: Level) -> AgentState {
         AgentState {
             my_id,
             game_state: gamestate::GameState::new(),
-            map: map::Map::new(level::example_level()),
+            map: map::Map::new(level),
             last_time: Instant::now(),
+            markers: MarkerList::new(),
+            weapon: WeaponKind::Gun,
         }
     }
 
@@ -64,6 +77,7 @@ impl AgentState {
         server_reader: &mut MessageReader,
         keyboard_state: &sdl2::keyboard::KeyboardState,
         mouse_state: &sdl2::mouse::RelativeMouseState,
+        sounds: &SoundAssets,
     ) -> StateResult {
         let elapsed = self.last_time.elapsed();
         self.last_time = Instant::now();
@@ -80,16 +94,34 @@ impl AgentState {
                     panic!("Got new ID after intialisation")
                 }
                 ServerMessage::GameState(state) => self.game_state = state,
-                ServerMessage::PlaySound(_sound, _pos) => {
-                    fn play_sound(soundeffect: &sdl2::mixer::Chunk) {
-                        if let Err(e) = sdl2::mixer::Channel::all().play(soundeffect, 0) {
-                            println!("SDL mixer error: {}", e);
-                        }
+                ServerMessage::PlaySound(sound, position) => {
+                    if let Some(me) = self.game_state.get_player_by_id(self.my_id) {
+                        let listener = Listener {
+                            position: me.position,
+                            rotation: me.rotation,
+                        };
+                        let muffled = audio::is_muffled(&self.map.graph, me.position, position);
+                        audio::play_at(sounds, sound, position, &listener, muffled);
                     }
                 }
+                // Levels only change between matches
+                ServerMessage::Level(_) => {}
+                ServerMessage::Marker(marker) => self.markers.add(marker),
+                // Teams are only joined from the menu
+                ServerMessage::JoinRejected(_) => {}
             }
         }
 
+        // We took over as dispatcher after ours left
+        let became_dispatcher = self
+            .game_state
+            .get_player_by_id(self.my_id)
+            .map(|me| me.player_type == player::PlayerType::Dispatcher)
+            .unwrap_or(false);
+        if became_dispatcher {
+            return StateResult::RoleChanged;
+        }
+
         let mut input = ClientInput::new();
         if keyboard_state.is_scancode_pressed(Scancode::W) {
             input.y_input -= 1.0;
@@ -106,13 +138,28 @@ impl AgentState {
         }
         input.rotation = mouse_state.x() as f32 * 0.001;
 
+        if keyboard_state.is_scancode_pressed(Scancode::Num1) {
+            self.weapon = WeaponKind::Gun;
+        }
+        if keyboard_state.is_scancode_pressed(Scancode::Num2) {
+            self.weapon = WeaponKind::Laser;
+        }
+        input.weapon = self.weapon;
+        input.fire = mouse_state.left();
+        input.interact = keyboard_state.is_scancode_pressed(Scancode::E);
+
+        self.markers.update(elapsed.as_secs_f32());
         self.map
             .update(elapsed.as_secs_f32(), &self.game_state, self.my_id);
 
         let input_message = ClientMessage::Input(input);
         crate::send_client_message(&input_message, &mut server_reader.stream);
 
-        StateResult::Continue
+        if self.game_state.is_finished() {
+            StateResult::GotoNext
+        } else {
+            StateResult::Continue
+        }
     }
 
     fn myself(&self) -> &player::Player {
@@ -123,12 +170,140 @@ impl AgentState {
     }
 }
 
+const FLAG_SIZE: f32 = 0.6;
+const AGENT_SIZE: f32 = 1.8;
+const MARKER_SIZE: f32 = 0.4;
+
+/// Height of the sprite of each kind of prop
+fn prop_size(kind: PropKind) -> f32 {
+    match kind {
+        PropKind::ServerRack => 1.8,
+        PropKind::Gpu => 0.4,
+        PropKind::OilBarrel => 0.9,
+        PropKind::Oven => 1.,
+    }
+}
+/// Markers float above everyone's heads
+const MARKER_HEIGHT: f32 = 2.;
+
+/// Where a point in the world ends up on the screen, if it is in view
+fn project_to_screen(
+    position: Vec3,
+    view: Mat4,
+    projection: Mat4,
+    (screen_w, screen_h): (u32, u32),
+) -> Option<(f32, f32)> {
+    let clip_position = projection * view * Vec4::new(position.x, position.y, position.z, 1.);
+    let screen_position = clip_position.truncated() / clip_position.w;
+
+    let in_view = clip_position.w > 0.
+        && screen_position.x.abs() <= 1.
+        && screen_position.y.abs() <= 1.;
+
+    if in_view {
+        let x = (screen_position.x + 1.) / 2. * screen_w as f32;
+        let y = (1. - screen_position.y) / 2. * screen_h as f32;
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+/// The text of a marker label and where on the screen to put it. Markers
+/// that are out of view are pointed at from the edge of the screen instead.
+fn marker_label(
+    marker: &Marker,
+    index: usize,
+    my_position: Vec2,
+    view: Mat4,
+    projection: Mat4,
+    screen_size: (u32, u32),
+) -> (String, (f32, f32), HorizontalAlign) {
+    let distance = (marker.position - my_position).mag();
+    let label = format!("{} {:.0}m", marker.kind.name(), distance);
+
+    let world_position = Vec3::new(marker.position.x, MARKER_HEIGHT, marker.position.y);
+    if let Some((x, y)) = project_to_screen(world_position, view, projection, screen_size) {
+        return (label, (x, y + 20.), HorizontalAlign::Center);
+    }
+
+    let (screen_w, screen_h) = (screen_size.0 as f32, screen_size.1 as f32);
+    let y = screen_h / 2. + index as f32 * 30.;
+    let view_position = view * Vec4::new(world_position.x, world_position.y, world_position.z, 1.);
+    if view_position.x < 0. {
+        (format!("< {}", label), (10., y), HorizontalAlign::Left)
+    } else {
+        (format!("{} >", label), (screen_w - 10., y), HorizontalAlign::Right)
+    }
+}
+
+/// Every agent other than ourselves that our team can see, along with the
+/// colour of their team
+fn other_agents(
+    game_state: &gamestate::GameState,
+    my_id: u64,
+) -> impl Iterator<Item = (&player::Player, (u8, u8, u8))> {
+    game_state.teams.values().flat_map(move |team| {
+        team.agents
+            .iter()
+            .filter(move |agent| agent.visible && agent.is_alive() && agent.id != my_id)
+            .map(move |agent| (agent, team.color))
+    })
+}
+
+fn scoreboard_text(game_state: &gamestate::GameState) -> String {
+    let scores = game_state
+        .sorted_teams()
+        .iter()
+        .map(|team| format!("{} {}", team.name, team.score))
+        .collect::<Vec<_>>()
+        .join("   ");
+    format!("{}   {}", scores, game_state.match_state.clock_text())
+}
+
+/// Health and weapon of the agent, shown in the bottom left corner
+fn status_text(me: &player::Player) -> String {
+    if !me.is_alive() {
+        return format!("Killed, respawning in {:.0}", me.respawn_time.max(0.).ceil());
+    }
+    let weapon = match me.weapon.charge_fraction() {
+        Some(charge) => format!("{} {:.0}%", me.weapon.kind.name(), charge * 100.),
+        None => String::from(me.weapon.kind.name()),
+    };
+    format!("Health {:.0}   {}   [1] Gun [2] Laser", me.health, weapon)
+}
+
+/// What the server rack next to the agent is up to, in objective matches
+fn hack_text(game_state: &GameState, me: &player::Player) -> Option<String> {
+    match game_state.config.mode {
+        Mode::Hacking(_) if me.is_alive() => {}
+        _ => return None,
+    }
+    let team_id = game_state.team_of_player(me.id)?;
+    let rack = game_state.props.iter()
+        .filter(|prop| prop.kind == PropKind::ServerRack)
+        .find(|prop| (prop.position - me.position).mag() <= HACK_RADIUS)?;
+
+    let progress = rack.hack_progress * 100.;
+    let text = if rack.hacked_by.is_some() {
+        String::from("Server rack hacked")
+    } else if rack.is_hackable_by(team_id) {
+        format!("Hold [E] to hack   {:.0}%", progress)
+    } else if rack.hack_progress > 0. {
+        format!("Hold [E] to repair   {:.0}% hacked", progress)
+    } else {
+        return None;
+    };
+    Some(text)
+}
+
 pub fn gameloop(
     sdl: sdl2::Sdl,
     event_pump: &mut EventPump,
     server_reader: &mut MessageReader,
     sounds: &SoundAssets,
     my_id: u64,
+    level: Level,
 ) -> (StateResult, sdl2::Sdl) {
     sdl.mouse().set_relative_mouse_mode(true);
 
@@ -153,7 +328,7 @@ pub fn gameloop(
         shader::compile_shader::<(), (), sprite::SpriteInterface>(&mut surface, vs, fs)
     };
 
-    let mut room_model = room::RoomModel::new(&mut surface);
+    let mut room_model = room::RoomModel::new(&mut surface, &level);
 
     let sprite_tess = surface
         .new_tess()
@@ -168,7 +343,7 @@ pub fn gameloop(
         GlyphBrushBuilder::using_font(font).build(&mut surface)
     };
 
-    let agent_state = &mut AgentState::new(my_id);
+    let agent_state = &mut AgentState::new(my_id, level);
 
     fn make_projection_matrix(surface: &surface::Sdl2Surface) -> Mat4 {
         let (width, height) = surface.window().size();
@@ -181,6 +356,20 @@ pub fn gameloop(
     let mut resize = false;
 
     let mut flower_sprite = sprite::load_sprite(&mut surface, "resources/flower.png");
+    let mut flag_sprite = sprite::load_sprite(&mut surface, "resources/flag.png");
+    let mut agent_sprite = sprite::load_sprite(&mut surface, "resources/agent.png");
+    let mut waypoint_sprite =
+        sprite::load_sprite(&mut surface, assets::marker_path(MarkerKind::Waypoint));
+    let mut danger_sprite =
+        sprite::load_sprite(&mut surface, assets::marker_path(MarkerKind::Danger));
+    let mut go_here_sprite =
+        sprite::load_sprite(&mut surface, assets::marker_path(MarkerKind::GoHere));
+    let mut server_rack_sprite =
+        sprite::load_sprite(&mut surface, assets::prop_path(PropKind::ServerRack));
+    let mut gpu_sprite = sprite::load_sprite(&mut surface, assets::prop_path(PropKind::Gpu));
+    let mut oil_barrel_sprite =
+        sprite::load_sprite(&mut surface, assets::prop_path(PropKind::OilBarrel));
+    let mut oven_sprite = sprite::load_sprite(&mut surface, assets::prop_path(PropKind::Oven));
 
     loop {
         for event in event_pump.poll_iter() {
@@ -211,14 +400,117 @@ pub fn gameloop(
         let mouse_state = event_pump.relative_mouse_state();
         let keyboard_state = event_pump.keyboard_state();
 
-        agent_state.update(server_reader, &keyboard_state, &mouse_state);
-        glyph_brush.process_queued(&mut surface);
+        let state_result = agent_state.update(server_reader, &keyboard_state, &mouse_state, sounds);
+        if state_result != StateResult::Continue {
+            let (sdl, ..) = surface.into_parts();
+            return (state_result, sdl);
+        }
+
+        let (screen_w, screen_h) = surface.window().drawable_size();
 
         let myself = agent_state.myself();
 
         let my_pos = Vec3::new(myself.position.x, 1.6, myself.position.y); // FIXME
         let view = Mat4::from_rotation_y(myself.rotation) * Mat4::from_translation(-my_pos);
 
+        glyph_brush.queue(
+            Section::default().add_text(
+                Text::new(&scoreboard_text(&agent_state.game_state))
+                    .with_color([1., 1., 1., 1.])
+                    .with_scale(32.),
+            ),
+        );
+        glyph_brush.queue(
+            Section::default()
+                .with_screen_position((10., screen_h as f32 - 10.))
+                .with_layout(Layout::default_single_line().v_align(VerticalAlign::Bottom))
+                .add_text(
+                    Text::new(&status_text(myself))
+                        .with_color([1., 1., 1., 1.])
+                        .with_scale(32.),
+                ),
+        );
+        if let Some(text) = hack_text(&agent_state.game_state, myself) {
+            glyph_brush.queue(
+                Section::default()
+                    .with_screen_position((screen_w as f32 / 2., screen_h as f32 / 2. + 40.))
+                    .with_layout(Layout::default_single_line().h_align(HorizontalAlign::Center))
+                    .add_text(Text::new(&text).with_color([1., 1., 1., 1.]).with_scale(32.)),
+            );
+        }
+        let killfeed = agent_state.game_state.killfeed.get_messages();
+        for (i, message) in killfeed.iter().enumerate() {
+            glyph_brush.queue(
+                Section::default()
+                    .with_screen_position((screen_w as f32 - 10., 10. + 28. * i as f32))
+                    .with_layout(Layout::default_single_line().h_align(HorizontalAlign::Right))
+                    .add_text(
+                        Text::new(&message.message)
+                            .with_color([1., 1., 1., 1.])
+                            .with_scale(24.),
+                    ),
+            );
+        }
+        glyph_brush.queue(
+            Section::default()
+                .with_screen_position((screen_w as f32 / 2., screen_h as f32 / 2.))
+                .with_layout(
+                    Layout::default_single_line()
+                        .h_align(HorizontalAlign::Center)
+                        .v_align(VerticalAlign::Center),
+                )
+                .add_text(Text::new("+").with_color([1., 1., 1., 0.8]).with_scale(32.)),
+        );
+        for (i, marker) in agent_state.markers.iter().enumerate() {
+            let (text, position, align) = marker_label(
+                marker,
+                i,
+                myself.position,
+                view,
+                projection,
+                (screen_w, screen_h),
+            );
+            let (r, g, b) = marker.kind.color();
+            glyph_brush.queue(
+                Section::default()
+                    .with_screen_position(position)
+                    .with_layout(Layout::default_single_line().h_align(align))
+                    .add_text(
+                        Text::new(&text)
+                            .with_color([r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.])
+                            .with_scale(24.),
+                    ),
+            );
+        }
+        // Name tags are drawn on top of everything, so they are only shown
+        // for the players that we can actually see
+        for (agent, color) in other_agents(&agent_state.game_state, agent_state.my_id) {
+            let (level, doors) = (&agent_state.map.level, &agent_state.game_state.doors);
+            if !collision::line_of_sight(level, doors, myself.position, agent.position) {
+                continue;
+            }
+            let head = Vec3::new(agent.position.x, AGENT_SIZE + 0.1, agent.position.y);
+            if let Some(position) = project_to_screen(head, view, projection, (screen_w, screen_h))
+            {
+                let (r, g, b) = color;
+                glyph_brush.queue(
+                    Section::default()
+                        .with_screen_position(position)
+                        .with_layout(
+                            Layout::default_single_line()
+                                .h_align(HorizontalAlign::Center)
+                                .v_align(VerticalAlign::Bottom),
+                        )
+                        .add_text(
+                            Text::new(&agent.name)
+                                .with_color([r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.])
+                                .with_scale(24.),
+                        ),
+                );
+            }
+        }
+        glyph_brush.process_queued(&mut surface);
+
         // Create a new dynamic pipeline that will render to the back buffer and must clear it
         // with pitch black prior to do any render to it.
         surface
@@ -227,9 +519,6 @@ pub fn gameloop(
                 &back_buffer,
                 &PipelineState::default(),
                 |mut pipeline, mut shd_gate| {
-                    // Draw text.
-                    glyph_brush.draw_queued(&mut pipeline, &mut shd_gate, 1024, 720)?;
-
                     let level = &agent_state.map.level;
                     room_model.draw(
                         &mut pipeline,
@@ -237,28 +526,134 @@ pub fn gameloop(
                         view,
                         projection,
                         &level.rooms,
+                        &agent_state.game_state.doors,
                     )?;
 
+                    // Start rendering things with the default render state provided by
+                    // luminance.
+                    let render_state = RenderState::default().set_blending(Blending {
+                        equation: Equation::Additive,
+                        src: Factor::SrcAlpha,
+                        dst: Factor::SrcAlphaComplement,
+                    });
+
                     let bound_tex = pipeline.bind_texture(&mut flower_sprite)?;
 
                     // Start shading with our program.
                     shd_gate.shade(&mut sprite_program, |mut iface, uni, mut rdr_gate| {
                         iface.set(&uni.tex, bound_tex.binding());
+                        iface.set(&uni.model, Mat4::identity().into());
                         iface.set(&uni.view, view.into());
                         iface.set(&uni.projection, projection.into());
+                        iface.set(&uni.tint, [0.; 4]);
 
-                        // Start rendering things with the default render state provided by
-                        // luminance.
-                        let render_state = RenderState::default().set_blending(Blending {
-                            equation: Equation::Additive,
-                            src: Factor::SrcAlpha,
-                            dst: Factor::SrcAlphaComplement,
-                        });
                         rdr_gate.render(&render_state, |mut tess_gate| {
                             tess_gate.render(&sprite_tess)
                         })
                     })?;
 
+                    let bound_tex = pipeline.bind_texture(&mut flag_sprite)?;
+                    let game_state = &agent_state.game_state;
+
+                    shd_gate.shade(&mut sprite_program, |mut iface, uni, mut rdr_gate| {
+                        iface.set(&uni.tex, bound_tex.binding());
+                        iface.set(&uni.view, view.into());
+                        iface.set(&uni.projection, projection.into());
+
+                        for flag in game_state.visible_flags() {
+                            let color = game_state.teams[&flag.team_id].color;
+                            let model =
+                                sprite::billboard(flag.position, FLAG_SIZE, myself.rotation);
+                            iface.set(&uni.model, model.into());
+                            iface.set(&uni.tint, sprite::team_tint(color));
+
+                            rdr_gate.render(&render_state, |mut tess_gate| {
+                                tess_gate.render(&sprite_tess)
+                            })?;
+                        }
+                        Ok(())
+                    })?;
+
+                    // Carried props are left out for the carrier, they would only
+                    // be in the way of the camera
+                    for prop in game_state.visible_props() {
+                        if prop.carrier == Some(myself.id) {
+                            continue;
+                        }
+                        let sprite = match prop.kind {
+                            PropKind::ServerRack => &mut server_rack_sprite,
+                            PropKind::Gpu => &mut gpu_sprite,
+                            PropKind::OilBarrel => &mut oil_barrel_sprite,
+                            PropKind::Oven => &mut oven_sprite,
+                        };
+                        let bound_tex = pipeline.bind_texture(sprite)?;
+                        let size = prop_size(prop.kind);
+                        let model = sprite::billboard(prop.position, size, myself.rotation);
+
+                        shd_gate.shade(&mut sprite_program, |mut iface, uni, mut rdr_gate| {
+                            iface.set(&uni.tex, bound_tex.binding());
+                            iface.set(&uni.model, model.into());
+                            iface.set(&uni.view, view.into());
+                            iface.set(&uni.projection, projection.into());
+                            iface.set(&uni.tint, [0.; 4]);
+
+                            rdr_gate.render(&render_state, |mut tess_gate| {
+                                tess_gate.render(&sprite_tess)
+                            })
+                        })?;
+                    }
+
+                    let bound_tex = pipeline.bind_texture(&mut agent_sprite)?;
+
+                    shd_gate.shade(&mut sprite_program, |mut iface, uni, mut rdr_gate| {
+                        iface.set(&uni.tex, bound_tex.binding());
+                        iface.set(&uni.view, view.into());
+                        iface.set(&uni.projection, projection.into());
+
+                        for (agent, color) in other_agents(game_state, myself.id) {
+                            let model =
+                                sprite::billboard(agent.position, AGENT_SIZE, myself.rotation);
+                            iface.set(&uni.model, model.into());
+                            iface.set(&uni.tint, sprite::team_tint(color));
+
+                            rdr_gate.render(&render_state, |mut tess_gate| {
+                                tess_gate.render(&sprite_tess)
+                            })?;
+                        }
+                        Ok(())
+                    })?;
+
+                    // Draw markers and text on top of everything else
+                    unsafe {
+                        gl::Clear(gl::DEPTH_BUFFER_BIT);
+                    }
+
+                    for marker in agent_state.markers.iter() {
+                        let sprite = match marker.kind {
+                            MarkerKind::Waypoint => &mut waypoint_sprite,
+                            MarkerKind::Danger => &mut danger_sprite,
+                            MarkerKind::GoHere => &mut go_here_sprite,
+                        };
+                        let bound_tex = pipeline.bind_texture(sprite)?;
+                        let position =
+                            Vec3::new(marker.position.x, MARKER_HEIGHT, marker.position.y);
+                        let model = sprite::billboard_at(position, MARKER_SIZE, myself.rotation);
+
+                        shd_gate.shade(&mut sprite_program, |mut iface, uni, mut rdr_gate| {
+                            iface.set(&uni.tex, bound_tex.binding());
+                            iface.set(&uni.model, model.into());
+                            iface.set(&uni.view, view.into());
+                            iface.set(&uni.projection, projection.into());
+                            iface.set(&uni.tint, [0.; 4]);
+
+                            rdr_gate.render(&render_state, |mut tess_gate| {
+                                tess_gate.render(&sprite_tess)
+                            })
+                        })?;
+                    }
+
+                    glyph_brush.draw_queued(&mut pipeline, &mut shd_gate, screen_w, screen_h)?;
+
                     Ok(())
                 },
             )
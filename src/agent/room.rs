@@ -1,21 +1,25 @@
 use luminance::context::GraphicsContext;
 use luminance::depth_test::DepthWrite;
 use luminance::face_culling::{FaceCulling, FaceCullingMode};
-use luminance::pipeline::{Pipeline, PipelineError, TextureBinding};
+use luminance::pipeline::{BoundTexture, Pipeline, PipelineError, TextureBinding};
 use luminance::pixel::{NormRGBA8UI, NormUnsigned};
 use luminance::render_state::RenderState;
-use luminance::shader::{Program, Uniform};
+use luminance::shader::{Program, ProgramInterface, Uniform};
 use luminance::shading_gate::ShadingGate;
 use luminance::tess::{Mode, Tess};
 use luminance::texture::{Dim2, Texture};
 use luminance_derive::{Semantics, UniformInterface, Vertex};
 use luminance_gl::GL33;
 
-use super::sprite::load_texture;
+use super::sprite::{load_texture, plain_texture};
+
+use std::collections::HashMap;
 
 use ultraviolet::{Mat3, Mat4, Vec2, Vec3, Vec4};
 
-use libplen::level;
+use libplen::door::{Door, DoorState};
+use libplen::level::{self, Level, RoomCoord};
+use libplen::math::Rect;
 
 use super::shader::compile_shader;
 use super::surface::Sdl2Surface;
@@ -27,6 +31,8 @@ pub enum WallSemantics {
     Position,
     #[sem(name = "uv", repr = "[f32; 2]", wrapper = "WallVertexUv")]
     Uv,
+    #[sem(name = "normal", repr = "[f32; 3]", wrapper = "WallVertexNormal")]
+    Normal,
 }
 
 #[repr(C)]
@@ -35,6 +41,7 @@ pub enum WallSemantics {
 struct WallVertex {
     position: WallVertexPosition,
     uv: WallVertexUv,
+    normal: WallVertexNormal,
 }
 
 #[derive(UniformInterface)]
@@ -43,13 +50,96 @@ pub struct WallInterface {
     pub view: Uniform<[[f32; 4]; 4]>,
     pub projection: Uniform<[[f32; 4]; 4]>,
     pub albedo: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    pub displacement: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    pub roughness: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    pub ambient_occlusion: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    pub tint: Uniform<[f32; 3]>,
+    pub camera_position: Uniform<[f32; 3]>,
+    pub light_position: Uniform<[f32; 3]>,
+    pub light_color: Uniform<[f32; 3]>,
+}
+
+type MaterialTexture = Texture<GL33, Dim2, NormRGBA8UI>;
+
+/// Greyscale maps describing the surface of a material. The normals are
+/// derived from the displacement map in the shader.
+struct SurfaceMaps {
+    displacement: MaterialTexture,
+    roughness: MaterialTexture,
+    ambient_occlusion: MaterialTexture,
 }
 
 struct WallMaterial {
     shader: Program<GL33, WallSemantics, (), WallInterface>,
-    albedo: Texture<GL33, Dim2, NormRGBA8UI>,
+    albedo: MaterialTexture,
+    maps: SurfaceMaps,
+}
+
+/// Floors and ceilings are drawn with the wall shader, only the textures and
+/// tint differ
+struct FloorMaterial {
+    albedo: MaterialTexture,
+    maps: SurfaceMaps,
+}
+
+/// The textures of a material while they are bound to the pipeline
+struct BoundMaterial<'a> {
+    albedo: BoundTexture<'a, GL33, Dim2, NormRGBA8UI>,
+    displacement: BoundTexture<'a, GL33, Dim2, NormRGBA8UI>,
+    roughness: BoundTexture<'a, GL33, Dim2, NormRGBA8UI>,
+    ambient_occlusion: BoundTexture<'a, GL33, Dim2, NormRGBA8UI>,
+}
+
+impl<'a> BoundMaterial<'a> {
+    fn bind(
+        pipeline: &'a Pipeline<'a, GL33>,
+        albedo: &'a mut MaterialTexture,
+        maps: &'a mut SurfaceMaps,
+    ) -> Result<Self, PipelineError> {
+        Ok(BoundMaterial {
+            albedo: pipeline.bind_texture(albedo)?,
+            displacement: pipeline.bind_texture(&mut maps.displacement)?,
+            roughness: pipeline.bind_texture(&mut maps.roughness)?,
+            ambient_occlusion: pipeline.bind_texture(&mut maps.ambient_occlusion)?,
+        })
+    }
+
+    fn set(&self, int: &mut ProgramInterface<GL33>, uni: &WallInterface, tint: [f32; 3]) {
+        int.set(&uni.albedo, self.albedo.binding());
+        int.set(&uni.displacement, self.displacement.binding());
+        int.set(&uni.roughness, self.roughness.binding());
+        int.set(&uni.ambient_occlusion, self.ambient_occlusion.binding());
+        int.set(&uni.tint, tint);
+    }
 }
 
+/// The light hanging from the ceiling of a room
+#[derive(Clone, Copy, Debug)]
+struct Light {
+    /// Relative to the room center
+    position: Vec3,
+    color: [f32; 3],
+}
+
+const ROOM_LIGHT: Light = Light {
+    position: Vec3 { x: 0., y: constants::CEILING_HEIGHT - 0.3, z: 0. },
+    color: [12., 11., 9.],
+};
+const CORRIDOR_LIGHT: Light = Light {
+    position: Vec3 { x: 0., y: constants::DOOR_HEIGHT - 0.2, z: 0. },
+    color: [5., 5.5, 6.5],
+};
+
+const WALL_TINT: [f32; 3] = [1., 1., 1.];
+const ROOM_FLOOR_TINT: [f32; 3] = [1., 1., 1.];
+/// Corridors get a cold, darker floor so they are easy to tell apart from
+/// full rooms
+const CORRIDOR_FLOOR_TINT: [f32; 3] = [0.5, 0.6, 0.75];
+const CEILING_TINT: [f32; 3] = [0.35, 0.35, 0.35];
+const CLOSED_DOOR_TINT: [f32; 3] = [0.45, 0.5, 0.55];
+/// Locked doors stand out so that agents know not to wait for them
+const LOCKED_DOOR_TINT: [f32; 3] = [0.8, 0.35, 0.1];
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Semantics)]
 pub enum HoleSemantics {
     #[sem(name = "position", repr = "[f32; 3]", wrapper = "HoleVertexPosition")]
@@ -73,27 +163,120 @@ struct HoleMaterial {
     shader: Program<GL33, HoleSemantics, (), HoleInterface>,
 }
 
+/// The walls, floor and ceiling of a room, relative to the room center
+struct Shape {
+    walls: Tess<GL33, WallVertex, u16>,
+    floor: Tess<GL33, WallVertex, u16>,
+    ceiling: Tess<GL33, WallVertex, u16>,
+}
+
+/// What a room is drawn with. Everything but the floor tint is the same for
+/// every room of a frame
+#[derive(Clone, Copy)]
+struct RoomDrawContext<'a> {
+    view_mat: Mat4,
+    projection_mat: Mat4,
+    camera_position: Vec3,
+    floor_tint: [f32; 3],
+    door_states: &'a [Door],
+}
+
 pub struct RoomModel {
-    wall_tess: Tess<GL33, WallVertex, u8>,
+    room_shape: Shape,
+    /// Corridors all look different, so they get their own shapes built
+    /// from the level
+    corridor_shapes: HashMap<RoomCoord, Shape>,
+    lights: HashMap<RoomCoord, Light>,
     doorway_tess: Tess<GL33, WallVertex, u8>,
     wall_material: WallMaterial,
     hole_tess: Tess<GL33, HoleVertex, u8>,
     hole_material: HoleMaterial,
+    doorway_floor_tess: Tess<GL33, WallVertex, u16>,
+    doorway_ceiling_tess: Tess<GL33, WallVertex, u16>,
+    door_panel_tess: Tess<GL33, WallVertex, u8>,
+    floor_material: FloorMaterial,
 }
 
 impl RoomModel {
-    pub fn new(surface: &mut Sdl2Surface) -> Self {
+    pub fn new(surface: &mut Sdl2Surface, level: &Level) -> Self {
+        let room_max = Vec2::new(constants::ROOM_WIDTH, constants::ROOM_LENGTH) * 0.5;
+        let room_area = Rect {
+            min: -room_max,
+            max: room_max,
+        };
+        // Doorways stick out of the room along +z, see `doorway_tess`
+        let doorway_area = Rect {
+            min: Vec2::new(-constants::DOOR_WIDTH / 2., 0.),
+            max: Vec2::new(constants::DOOR_WIDTH / 2., constants::DOORWAY_LENGTH),
+        };
+
+        let room_shape = Shape {
+            walls: wall_tess(surface),
+            floor: horizontal_tess(surface, &[room_area], 0., true),
+            ceiling: horizontal_tess(surface, &[room_area], constants::CEILING_HEIGHT, false),
+        };
+
+        let mut corridor_shapes = HashMap::new();
+        let mut lights = HashMap::new();
+        for (col, rooms) in level.rooms.iter().enumerate() {
+            for (row, room) in rooms.iter().enumerate() {
+                match room {
+                    level::Room::FullRoom(_) => {
+                        lights.insert((col, row), ROOM_LIGHT);
+                    }
+                    level::Room::Corridor(doors) => {
+                        let shape = corridor_shape(surface, (col, row), doors);
+                        corridor_shapes.insert((col, row), shape);
+                        lights.insert((col, row), CORRIDOR_LIGHT);
+                    }
+                    level::Room::Empty => {}
+                }
+            }
+        }
+
         Self {
-            wall_tess: wall_tess(surface),
+            room_shape,
+            corridor_shapes,
+            lights,
             doorway_tess: doorway_tess(surface),
             hole_tess: hole_tess(surface),
+            doorway_floor_tess: horizontal_tess(surface, &[doorway_area], 0., true),
+            doorway_ceiling_tess: horizontal_tess(
+                surface,
+                &[doorway_area],
+                constants::DOOR_HEIGHT,
+                false,
+            ),
+            door_panel_tess: door_panel_tess(surface),
             wall_material: WallMaterial {
                 shader: compile_shader(
                     surface,
                     include_str!("../../shaders/wall.vert"),
                     include_str!("../../shaders/wall.frag"),
                 ),
-                albedo: load_texture(surface, "resources/bricks/Bricks025_1K_Color.png"),
+                // Only the surface maps of the bricks are shipped, the shading from the
+                // displacement and occlusion maps is what makes them look like bricks
+                albedo: plain_texture(surface, 200),
+                maps: SurfaceMaps {
+                    displacement: load_texture(
+                        surface,
+                        "resources/bricks/Bricks025_1K_Displacement.png",
+                    ),
+                    roughness: load_texture(surface, "resources/bricks/Bricks025_1K_Roughness.png"),
+                    ambient_occlusion: load_texture(
+                        surface,
+                        "resources/bricks/Bricks025_1K_AmbientOcclusion.png",
+                    ),
+                },
+            },
+            floor_material: FloorMaterial {
+                albedo: load_texture(surface, "resources/floor.png"),
+                // The floor art is flat and fairly rough all over
+                maps: SurfaceMaps {
+                    displacement: plain_texture(surface, 0),
+                    roughness: plain_texture(surface, 200),
+                    ambient_occlusion: plain_texture(surface, 255),
+                },
             },
             hole_material: HoleMaterial {
                 shader: compile_shader(
@@ -112,11 +295,20 @@ impl RoomModel {
         view_mat: Mat4,
         projection_mat: Mat4,
         rooms: I,
+        door_states: &[Door],
     ) -> Result<(), PipelineError>
     where
         I: IntoIterator<Item = J>,
         J: IntoIterator<Item = &'r level::Room>,
     {
+        let frame = RoomDrawContext {
+            view_mat,
+            projection_mat,
+            camera_position: view_mat.inversed().cols[3].truncated(),
+            floor_tint: ROOM_FLOOR_TINT,
+            door_states,
+        };
+
         unsafe {
             gl::Enable(gl::STENCIL_TEST);
             gl::StencilFunc(gl::NOTEQUAL, 1, !0);
@@ -125,24 +317,22 @@ impl RoomModel {
 
         for (column, rooms) in rooms.into_iter().enumerate() {
             for (row, room) in rooms.into_iter().enumerate() {
-                match room {
-                    level::Room::Corridor(doorways) | level::Room::FullRoom(doorways) => {
-                        self.draw_one(
-                            pipeline,
-                            shd_gate,
-                            view_mat,
-                            projection_mat,
-                            (column, row),
-                            doorways,
-                        )?;
-                    }
-                    level::Room::Empty => {}
-                }
+                let (doorways, floor_tint) = match room {
+                    level::Room::Corridor(doorways) => (doorways, CORRIDOR_FLOOR_TINT),
+                    level::Room::FullRoom(doorways) => (doorways, ROOM_FLOOR_TINT),
+                    level::Room::Empty => continue,
+                };
+                let context = RoomDrawContext { floor_tint, ..frame };
+                self.draw_one(pipeline, shd_gate, context, (column, row), doorways)?;
             }
         }
 
+        // The stencil is only used for cutting doorways out of the walls,
+        // anything drawn after the rooms just has to be behind or in front
+        // of them
         unsafe {
             gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+            gl::Disable(gl::STENCIL_TEST);
         }
 
         Ok(())
@@ -152,23 +342,39 @@ impl RoomModel {
         &mut self,
         pipeline: &mut Pipeline<GL33>,
         shd_gate: &mut ShadingGate<GL33>,
-        view_mat: Mat4,
-        projection_mat: Mat4,
+        context: RoomDrawContext,
         room_coord: (usize, usize),
         doors: &[(i8, i8)],
     ) -> Result<(), PipelineError> {
+        let RoomDrawContext {
+            view_mat,
+            projection_mat,
+            camera_position,
+            floor_tint,
+            door_states,
+        } = context;
         let Self {
-            wall_tess,
+            room_shape,
+            corridor_shapes,
+            lights,
             doorway_tess,
             hole_tess,
             wall_material:
                 WallMaterial {
                     shader: wall_shader,
                     albedo: wall_albedo,
+                    maps: wall_maps,
                 },
             hole_material: HoleMaterial {
                 shader: hole_shader,
             },
+            doorway_floor_tess,
+            doorway_ceiling_tess,
+            door_panel_tess,
+            floor_material: FloorMaterial {
+                albedo: floor_albedo,
+                maps: floor_maps,
+            },
         } = self;
 
         let (column, row) = room_coord;
@@ -193,7 +399,11 @@ impl RoomModel {
             room_model_mat.translated(&translation) * rotation
         };
 
-        for &offset in doors {
+        // Corridors end in their doorways instead of a wall with a hole
+        let corridor_shape = corridor_shapes.get(&room_coord);
+        let hole_doors = if corridor_shape.is_some() { &[][..] } else { doors };
+
+        for &offset in hole_doors {
             let model_mat = door_transform(offset);
             shd_gate.shade(hole_shader, |mut int, uni, mut rdr_gate| {
                 int.set(&uni.model, model_mat.into());
@@ -215,28 +425,66 @@ impl RoomModel {
             ..Default::default()
         });
 
-        let wall_albedo = pipeline.bind_texture(wall_albedo)?;
+        let wall = BoundMaterial::bind(pipeline, wall_albedo, wall_maps)?;
+        let floor = BoundMaterial::bind(pipeline, floor_albedo, floor_maps)?;
+
+        let light = lights[&room_coord];
+        let light_position = translation + light.position;
 
+        let shape = corridor_shape.unwrap_or(room_shape);
         shd_gate.shade(wall_shader, |mut int, uni, mut rdr_gate| {
             int.set(&uni.model, room_model_mat.into());
             int.set(&uni.view, view_mat.into());
             int.set(&uni.projection, projection_mat.into());
-            int.set(&uni.albedo, wall_albedo.binding());
+            int.set(&uni.camera_position, camera_position.into());
+            int.set(&uni.light_position, light_position.into());
+            int.set(&uni.light_color, light.color);
+
+            wall.set(&mut int, uni, WALL_TINT);
+            rdr_gate.render(&render_state, |mut tess_gate| tess_gate.render(&shape.walls))?;
+
+            floor.set(&mut int, uni, floor_tint);
+            rdr_gate.render(&render_state, |mut tess_gate| tess_gate.render(&shape.floor))?;
 
-            rdr_gate.render(&render_state, |mut tess_gate| tess_gate.render(&*wall_tess))
+            floor.set(&mut int, uni, CEILING_TINT);
+            rdr_gate.render(&render_state, |mut tess_gate| tess_gate.render(&shape.ceiling))
         })?;
 
         for &offset in doors {
             let model_mat = door_transform(offset);
+            let panel_tint = door_panel_tint(door_states, room_coord, offset);
             shd_gate.shade(wall_shader, |mut int, uni, mut rdr_gate| {
                 int.set(&uni.model, model_mat.into());
                 int.set(&uni.view, view_mat.into());
                 int.set(&uni.projection, projection_mat.into());
-                int.set(&uni.albedo, wall_albedo.binding());
+                int.set(&uni.camera_position, camera_position.into());
+                int.set(&uni.light_position, light_position.into());
+                int.set(&uni.light_color, light.color);
 
+                wall.set(&mut int, uni, WALL_TINT);
                 rdr_gate.render(&render_state, |mut tess_gate| {
                     tess_gate.render(&*doorway_tess)
-                })
+                })?;
+
+                floor.set(&mut int, uni, floor_tint);
+                rdr_gate.render(&render_state, |mut tess_gate| {
+                    tess_gate.render(&*doorway_floor_tess)
+                })?;
+
+                floor.set(&mut int, uni, CEILING_TINT);
+                rdr_gate.render(&render_state, |mut tess_gate| {
+                    tess_gate.render(&*doorway_ceiling_tess)
+                })?;
+
+                match panel_tint {
+                    Some(tint) => {
+                        floor.set(&mut int, uni, tint);
+                        rdr_gate.render(&render_state, |mut tess_gate| {
+                            tess_gate.render(&*door_panel_tess)
+                        })
+                    }
+                    None => Ok(()),
+                }
             })?;
         }
 
@@ -248,9 +496,9 @@ impl RoomModel {
     }
 }
 
-fn wall_tess(surface: &mut impl GraphicsContext<Backend = GL33>) -> Tess<GL33, WallVertex, u8> {
+fn wall_tess(surface: &mut impl GraphicsContext<Backend = GL33>) -> Tess<GL33, WallVertex, u16> {
     let mut vertices: Vec<WallVertex> = vec![];
-    let mut indices: Vec<u8> = vec![];
+    let mut indices: Vec<u16> = vec![];
 
     let rot_ninety_degrees = Mat3::new(
         Vec3::new(0., 0., 1.),
@@ -261,8 +509,10 @@ fn wall_tess(surface: &mut impl GraphicsContext<Backend = GL33>) -> Tess<GL33, W
     let mut rot_matrix = Mat3::identity();
     for i in 0..4_i32 {
         let flip_uv = (i / 2 * 2 - 1) as f32;
+        // The walls face the inside of the room
+        let normal = rot_matrix * Vec3::new(0., 0., -1.);
 
-        let index = vertices.len() as u8;
+        let index = vertices.len() as u16;
 
         for x in 0..2 {
             for y in 0..2 {
@@ -281,6 +531,7 @@ fn wall_tess(surface: &mut impl GraphicsContext<Backend = GL33>) -> Tess<GL33, W
                 vertices.push(WallVertex {
                     position: WallVertexPosition::new(pos.into()),
                     uv: WallVertexUv::new(uv.into()),
+                    normal: WallVertexNormal::new(normal.into()),
                 });
             }
         }
@@ -351,6 +602,7 @@ fn doorway_tess(surface: &mut Sdl2Surface) -> Tess<GL33, WallVertex, u8> {
                 vertices.push(WallVertex {
                     position: WallVertexPosition::new(pos.into()),
                     uv: WallVertexUv::new(uv.into()),
+                    normal: WallVertexNormal::new([-1., 0., 0.]),
                 });
             }
         }
@@ -371,3 +623,198 @@ fn doorway_tess(surface: &mut Sdl2Surface) -> Tess<GL33, WallVertex, u8> {
         .build()
         .unwrap()
 }
+
+/// The panel shutting a doorway halfway through, facing the room that the
+/// doorway belongs to. The room on the other side draws its own panel.
+fn door_panel_tess(surface: &mut Sdl2Surface) -> Tess<GL33, WallVertex, u8> {
+    let mut vertices = vec![];
+
+    for x in 0..2 {
+        for y in 0..2 {
+            let x = (x * 2 - 1) as f32 * constants::DOOR_WIDTH / 2.;
+            let y = y as f32 * constants::DOOR_HEIGHT;
+            vertices.push(WallVertex {
+                position: WallVertexPosition::new([x, y, constants::DOORWAY_LENGTH / 2.]),
+                uv: WallVertexUv::new([x * 0.5, -y * 0.5]),
+                normal: WallVertexNormal::new([0., 0., -1.]),
+            });
+        }
+    }
+
+    let indices = vec![0, 1, 2, 3, 2, 1];
+
+    surface
+        .new_tess()
+        .set_mode(Mode::Triangle)
+        .set_vertices(vertices)
+        .set_indices(indices)
+        .build()
+        .unwrap()
+}
+
+/// The tint of the panel in the doorway from `room` in the direction of
+/// `offset`, or None if the door is open
+fn door_panel_tint(door_states: &[Door], room: RoomCoord, offset: (i8, i8)) -> Option<[f32; 3]> {
+    let target = level::door_target(room, offset)?;
+    let door = door_states.iter().find(|door| door.connects(room, target))?;
+    match door.state {
+        DoorState::Open => None,
+        DoorState::Closed => Some(CLOSED_DOOR_TINT),
+        DoorState::Locked => Some(LOCKED_DOOR_TINT),
+    }
+}
+
+/// Horizontal quads covering `areas` in the xz plane at `height`, facing up
+/// for floors and down for ceilings
+fn horizontal_tess(
+    surface: &mut Sdl2Surface,
+    areas: &[Rect],
+    height: f32,
+    facing_up: bool,
+) -> Tess<GL33, WallVertex, u16> {
+    let mut vertices: Vec<WallVertex> = vec![];
+    let mut indices: Vec<u16> = vec![];
+    let normal = if facing_up { [0., 1., 0.] } else { [0., -1., 0.] };
+
+    for area in areas {
+        let index = vertices.len() as u16;
+
+        for x in 0..2 {
+            for z in 0..2 {
+                let x = if x == 0 { area.min.x } else { area.max.x };
+                let z = if z == 0 { area.min.y } else { area.max.y };
+
+                let uv = Vec2::new(x, z) * 0.5;
+
+                vertices.push(WallVertex {
+                    position: WallVertexPosition::new([x, height, z]),
+                    uv: WallVertexUv::new(uv.into()),
+                    normal: WallVertexNormal::new(normal),
+                });
+            }
+        }
+
+        let quad: [u16; 6] = if facing_up {
+            [0, 1, 2, 3, 2, 1]
+        } else {
+            [0, 2, 1, 3, 1, 2]
+        };
+        indices.extend(quad.iter().map(|i| index + i));
+    }
+
+    surface
+        .new_tess()
+        .set_mode(Mode::Triangle)
+        .set_vertices(vertices)
+        .set_indices(indices)
+        .build()
+        .unwrap()
+}
+
+/// The narrow passages of a corridor, as given by `level::corridor_areas`,
+/// relative to the room center. Corridors are only as high as their doors.
+fn corridor_shape(surface: &mut Sdl2Surface, room: RoomCoord, doors: &[(i8, i8)]) -> Shape {
+    let center = level::room_center(room.0, room.1);
+    let areas = level::corridor_areas(room, doors)
+        .iter()
+        .map(|area| Rect {
+            min: area.min - center,
+            max: area.max - center,
+        })
+        .collect::<Vec<_>>();
+
+    let mut vertices: Vec<WallVertex> = vec![];
+    let mut indices: Vec<u16> = vec![];
+
+    for (start, end) in outline(&areas) {
+        let index = vertices.len() as u16;
+        let along = (end - start).normalized();
+        let normal = Vec3::new(along.y, 0., -along.x);
+
+        for &point in &[start, end] {
+            for &y in &[0., constants::DOOR_HEIGHT] {
+                let pos = Vec3::new(point.x, y, point.y);
+                let uv = Vec2::new(pos.x + pos.z, -pos.y) * 0.5;
+
+                vertices.push(WallVertex {
+                    position: WallVertexPosition::new(pos.into()),
+                    uv: WallVertexUv::new(uv.into()),
+                    normal: WallVertexNormal::new(normal.into()),
+                });
+            }
+        }
+
+        indices.extend([0, 1, 2, 3, 2, 1].iter().map(|i| index + i));
+    }
+
+    let walls = surface
+        .new_tess()
+        .set_mode(Mode::Triangle)
+        .set_vertices(vertices)
+        .set_indices(indices)
+        .build()
+        .unwrap();
+
+    Shape {
+        walls,
+        floor: horizontal_tess(surface, &areas, 0., true),
+        ceiling: horizontal_tess(surface, &areas, constants::DOOR_HEIGHT, false),
+    }
+}
+
+/// The edges around the union of `areas`, directed so that walls built
+/// along them face the inside. Edges on the room walls are left out, those
+/// are where a corridor meets its doorways.
+fn outline(areas: &[Rect]) -> Vec<(Vec2, Vec2)> {
+    const EPSILON: f32 = 1e-3;
+
+    let room_max = Vec2::new(constants::ROOM_WIDTH, constants::ROOM_LENGTH) * 0.5;
+    let on_room_wall = |p: Vec2| {
+        (p.x.abs() - room_max.x).abs() < EPSILON || (p.y.abs() - room_max.y).abs() < EPSILON
+    };
+
+    let mut edges = vec![];
+    for (i, area) in areas.iter().enumerate() {
+        let corners = [
+            area.min,
+            Vec2::new(area.min.x, area.max.y),
+            area.max,
+            Vec2::new(area.max.x, area.min.y),
+        ];
+
+        for k in 0..4 {
+            let (start, end) = (corners[k], corners[(k + 1) % 4]);
+            let length = (end - start).mag();
+            let along = (end - start) / length;
+            let outwards = Vec2::new(-along.y, along.x);
+
+            // Split the edge wherever another area starts or ends, each
+            // piece is then either completely on the outline or not at all
+            let mut cuts = vec![0., length];
+            for other in areas {
+                for &corner in &[other.min, other.max] {
+                    let t = (corner - start).dot(along);
+                    if t > EPSILON && t < length - EPSILON {
+                        cuts.push(t);
+                    }
+                }
+            }
+            cuts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            for piece in cuts.windows(2) {
+                let middle = start + along * (piece[0] + piece[1]) / 2.;
+                let outside = middle + outwards * EPSILON;
+                let inside = middle - outwards * EPSILON;
+
+                let is_outline = !areas.iter().any(|other| other.contains(outside));
+                // Overlapping areas can share an edge, only keep it once
+                let seen_before = areas[..i].iter().any(|other| other.contains(inside));
+
+                if is_outline && !seen_before && !on_room_wall(middle) {
+                    edges.push((start + along * piece[0], start + along * piece[1]));
+                }
+            }
+        }
+    }
+    edges
+}
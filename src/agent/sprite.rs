@@ -9,21 +9,48 @@ use luminance_derive::UniformInterface;
 use luminance_gl::GL33;
 
 use sdl2::image::LoadSurface;
+use sdl2::pixels::PixelFormatEnum;
+
+use ultraviolet::{Mat4, Vec2, Vec3};
 
 pub type Sprite = Texture<GL33, Dim2, NormRGBA8UI>;
 
 #[derive(UniformInterface)]
 pub struct SpriteInterface {
     pub tex: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    pub model: Uniform<[[f32; 4]; 4]>,
     pub view: Uniform<[[f32; 4]; 4]>,
     pub projection: Uniform<[[f32; 4]; 4]>,
+    pub tint: Uniform<[f32; 4]>,
+}
+
+/// Model matrix for a sprite standing on the floor at `position`, turned to
+/// face a camera with the given rotation around the y axis
+pub fn billboard(position: Vec2, size: f32, camera_rotation: f32) -> Mat4 {
+    billboard_at(Vec3::new(position.x, size / 2., position.y), size, camera_rotation)
+}
+
+/// Like `billboard`, but centred on a point anywhere in the world
+pub fn billboard_at(position: Vec3, size: f32, camera_rotation: f32) -> Mat4 {
+    Mat4::from_translation(position)
+        * Mat4::from_rotation_y(-camera_rotation)
+        * Mat4::from_scale(size)
+}
+
+pub fn team_tint((r, g, b): (u8, u8, u8)) -> [f32; 4] {
+    [r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.]
 }
 
 pub fn load_texture(
     surface: &mut impl GraphicsContext<Backend = GL33>,
     path: impl AsRef<Path>,
 ) -> Texture<GL33, Dim2, NormRGBA8UI> {
-    let image = sdl2::surface::Surface::from_file(path).unwrap();
+    // Greyscale maps like the ones in resources/bricks load with a different
+    // pixel format, so everything is converted to plain RGBA first
+    let image = sdl2::surface::Surface::from_file(path)
+        .unwrap()
+        .convert_format(PixelFormatEnum::RGBA32)
+        .unwrap();
     let (width, height) = image.size();
     let bytes = image.without_lock().unwrap();
 
@@ -44,6 +71,18 @@ pub fn load_texture(
     texture
 }
 
+/// A single pixel texture with all channels set to `value`, for materials
+/// that have no map of their own
+pub fn plain_texture(
+    surface: &mut impl GraphicsContext<Backend = GL33>,
+    value: u8,
+) -> Texture<GL33, Dim2, NormRGBA8UI> {
+    let mut texture = Texture::new(surface, [1, 1], 0, Sampler::default())
+        .expect("Failed to create texture");
+    texture.upload_raw(GenMipmaps::No, &[value; 4]).unwrap();
+    texture
+}
+
 pub fn load_sprite(
     surface: &mut impl GraphicsContext<Backend = GL33>,
     path: impl AsRef<Path>,
@@ -1,14 +1,25 @@
+use enum_map::EnumMap;
 use sdl2::image::LoadTexture;
 use sdl2::mixer::Chunk;
 use sdl2::render::{Texture, TextureCreator};
 use sdl2::video::WindowContext;
 
+use libplen::marker::MarkerKind;
+use libplen::messages::SoundEffect;
+use libplen::prop::PropKind;
+
 pub struct Assets<'ttf, 'r> {
     pub font: sdl2::ttf::Font<'ttf, 'r>,
 
     pub menu_background: Texture<'r>,
     pub end_background: Texture<'r>,
-    pub sounds: SoundAssets,
+    pub waypoint_marker: Texture<'r>,
+    pub danger_marker: Texture<'r>,
+    pub go_here_marker: Texture<'r>,
+    pub server_rack: Texture<'r>,
+    pub gpu: Texture<'r>,
+    pub oil_barrel: Texture<'r>,
+    pub oven: Texture<'r>,
 }
 
 pub struct SoundAssets {
@@ -21,13 +32,14 @@ pub struct SoundAssets {
     pub gun: Chunk,
     pub laser_fire_sound: Chunk,
     pub laser_charge_sound: Chunk,
+    /// Low-passed copies of the effects, for sounds coming from another room
+    muffled: EnumMap<SoundEffect, Chunk>,
 }
 
 impl<'ttf, 'r> Assets<'ttf, 'r> {
     pub fn new(
         texture_creator: &'r TextureCreator<WindowContext>,
         ttf_context: &'ttf sdl2::ttf::Sdl2TtfContext,
-        sounds: SoundAssets,
     ) -> Assets<'ttf, 'r> {
         let load_tex = |path: &str| {
             let mut tex = texture_creator
@@ -43,13 +55,56 @@ impl<'ttf, 'r> Assets<'ttf, 'r> {
                 .expect("Could not find font!"),
             menu_background: load_tex("resources/menu_background.png"),
             end_background: load_tex("resources/endscreen.png"),
-            sounds: SoundAssets::new(),
+            waypoint_marker: load_tex(marker_path(MarkerKind::Waypoint)),
+            danger_marker: load_tex(marker_path(MarkerKind::Danger)),
+            go_here_marker: load_tex(marker_path(MarkerKind::GoHere)),
+            server_rack: load_tex(prop_path(PropKind::ServerRack)),
+            gpu: load_tex(prop_path(PropKind::Gpu)),
+            oil_barrel: load_tex(prop_path(PropKind::OilBarrel)),
+            oven: load_tex(prop_path(PropKind::Oven)),
+        }
+    }
+
+    pub fn marker(&self, kind: MarkerKind) -> &Texture<'r> {
+        match kind {
+            MarkerKind::Waypoint => &self.waypoint_marker,
+            MarkerKind::Danger => &self.danger_marker,
+            MarkerKind::GoHere => &self.go_here_marker,
+        }
+    }
+
+    pub fn prop(&self, kind: PropKind) -> &Texture<'r> {
+        match kind {
+            PropKind::ServerRack => &self.server_rack,
+            PropKind::Gpu => &self.gpu,
+            PropKind::OilBarrel => &self.oil_barrel,
+            PropKind::Oven => &self.oven,
         }
     }
 }
 
+pub fn marker_path(kind: MarkerKind) -> &'static str {
+    match kind {
+        MarkerKind::Waypoint => "resources/markers/waypoint.png",
+        MarkerKind::Danger => "resources/markers/danger.png",
+        MarkerKind::GoHere => "resources/markers/go_here.png",
+    }
+}
+
+pub fn prop_path(kind: PropKind) -> &'static str {
+    match kind {
+        PropKind::ServerRack => "resources/server_rack.png",
+        PropKind::Gpu => "resources/gpu.png",
+        PropKind::OilBarrel => "resources/oil_barrel.png",
+        PropKind::Oven => "resources/owen.png",
+    }
+}
+
 impl SoundAssets {
     pub fn new() -> Self {
+        let load = |path: &str| Chunk::from_file(path).unwrap();
+        let muffled = EnumMap::from(|sound| muffle(&load(effect_path(sound))));
+
         let mut sounds = SoundAssets {
             achtung_blitzkrieg_engine: Chunk::from_file(
                 "resources/audio/achtungblitzkrieg-engine.ogg",
@@ -60,11 +115,12 @@ impl SoundAssets {
             howdy_cowboy_engine: Chunk::from_file("resources/audio/howdycowboy-engine.ogg")
                 .unwrap(),
             suka_blyat_engine: Chunk::from_file("resources/audio/sukablyat-engine.ogg").unwrap(),
-            powerup: Chunk::from_file("resources/audio/powerup.ogg").unwrap(),
-            explosion: Chunk::from_file("resources/audio/explosion.ogg").unwrap(),
-            gun: Chunk::from_file("resources/audio/gun.ogg").unwrap(),
-            laser_fire_sound: Chunk::from_file("resources/audio/laserfire.ogg").unwrap(),
-            laser_charge_sound: Chunk::from_file("resources/audio/lasercharge.ogg").unwrap(),
+            powerup: load(effect_path(SoundEffect::Powerup)),
+            explosion: load(effect_path(SoundEffect::Explosion)),
+            gun: load(effect_path(SoundEffect::Gun)),
+            laser_fire_sound: load(effect_path(SoundEffect::LaserFire)),
+            laser_charge_sound: load(effect_path(SoundEffect::LaserCharge)),
+            muffled,
         };
 
         // Volume is on a scale from 0 to 128
@@ -75,4 +131,52 @@ impl SoundAssets {
 
         sounds
     }
+
+    pub fn effect(&self, sound: SoundEffect) -> &Chunk {
+        match sound {
+            SoundEffect::Powerup => &self.powerup,
+            SoundEffect::Explosion => &self.explosion,
+            SoundEffect::Gun => &self.gun,
+            SoundEffect::LaserCharge => &self.laser_charge_sound,
+            SoundEffect::LaserFire => &self.laser_fire_sound,
+        }
+    }
+
+    pub fn muffled_effect(&self, sound: SoundEffect) -> &Chunk {
+        &self.muffled[sound]
+    }
+}
+
+fn effect_path(sound: SoundEffect) -> &'static str {
+    match sound {
+        SoundEffect::Powerup => "resources/audio/powerup.ogg",
+        SoundEffect::Explosion => "resources/audio/explosion.ogg",
+        SoundEffect::Gun => "resources/audio/gun.ogg",
+        SoundEffect::LaserCharge => "resources/audio/lasercharge.ogg",
+        SoundEffect::LaserFire => "resources/audio/laserfire.ogg",
+    }
+}
+
+/// How much of each new sample makes it through the low-pass filter of
+/// `muffle`. Lower values muffle more.
+const MUFFLE_FACTOR: f32 = 0.12;
+
+/// A copy of `chunk` that sounds like it is coming through a wall, made by
+/// running a simple low-pass filter over it. Chunks are always stereo 16 bit
+/// samples, see the mixer setup in `client.rs`.
+fn muffle(chunk: &Chunk) -> Chunk {
+    let samples = unsafe {
+        let raw = &*chunk.raw;
+        std::slice::from_raw_parts(raw.abuf as *const i16, raw.alen as usize / 2)
+    };
+
+    let mut filtered = Vec::with_capacity(samples.len());
+    let mut previous = [0f32; 2];
+    for (i, &sample) in samples.iter().enumerate() {
+        let channel = &mut previous[i % 2];
+        *channel += (sample as f32 - *channel) * MUFFLE_FACTOR;
+        filtered.push(*channel as i16);
+    }
+
+    Chunk::from_raw_buffer(filtered.into_boxed_slice()).unwrap()
 }
@@ -0,0 +1,82 @@
+use ultraviolet::Rotor2;
+
+use libplen::constants::HEARING_DISTANCE;
+use libplen::level::{Location, RoomCoord, RoomGraph};
+use libplen::math::{vec2, Vec2};
+use libplen::messages::SoundEffect;
+
+use crate::assets::SoundAssets;
+
+/// Sounds from other rooms are muffled and also sound further away than
+/// they are
+const MUFFLED_DISTANCE_FACTOR: f32 = 1.5;
+
+/// Where the sounds are heard from
+pub struct Listener {
+    pub position: Vec2,
+    /// Same as `Player::rotation`, 0 is looking towards negative y
+    pub rotation: f32,
+}
+
+/// Plays `sound` on a free mixer channel, panned and attenuated according to
+/// where it happened relative to the listener
+pub fn play_at(
+    sounds: &SoundAssets,
+    sound: SoundEffect,
+    position: Vec2,
+    listener: &Listener,
+    muffled: bool,
+) {
+    let offset = position - listener.position;
+    let mut distance = offset.mag();
+    if muffled {
+        distance *= MUFFLED_DISTANCE_FACTOR;
+    }
+    if distance > HEARING_DISTANCE {
+        return;
+    }
+
+    let rotor = Rotor2::from_angle(-listener.rotation);
+    let forward = vec2(0., -1.).rotated_by(rotor);
+    let right = vec2(1., 0.).rotated_by(rotor);
+    // The mixer wants degrees going clockwise with 0 straight ahead
+    let angle = offset.dot(right).atan2(offset.dot(forward)).to_degrees();
+    let angle = (angle.round() as i16 + 360) % 360;
+    let distance = (distance / HEARING_DISTANCE * 255.) as u8;
+
+    let chunk = if muffled {
+        sounds.muffled_effect(sound)
+    } else {
+        sounds.effect(sound)
+    };
+
+    // The position has to be set before playing, or the start of the sound
+    // would come from wherever the channel was last used
+    let channel = match sdl2::mixer::Group::default().find_available() {
+        Some(channel) => channel,
+        None => return,
+    };
+    let result = channel
+        .set_position(angle, distance)
+        .and_then(|_| channel.play(chunk, 0))
+        .map(|_| ());
+    if let Err(e) = result {
+        println!("SDL mixer error: {}", e);
+    }
+}
+
+/// Whether a sound between the two positions has to come through a doorway
+/// or wall rather than being in the same room as the listener
+pub fn is_muffled(graph: &RoomGraph, listener: Vec2, source: Vec2) -> bool {
+    let rooms = |position| -> Vec<RoomCoord> {
+        match graph.locate(position) {
+            Some(Location::Room(room)) => vec![room],
+            // Standing in a doorway is being in both rooms at once
+            Some(Location::Doorway(a, b)) => vec![a, b],
+            None => vec![],
+        }
+    };
+
+    let listener_rooms = rooms(listener);
+    !rooms(source).iter().any(|room| listener_rooms.contains(room))
+}
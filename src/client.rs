@@ -1,6 +1,8 @@
 mod agent;
 mod assets;
+mod audio;
 mod dispatcher;
+mod endscreen;
 mod map;
 mod menu;
 mod rendering;
@@ -19,6 +21,7 @@ use sdl2::video::Window;
 
 use assets::{Assets, SoundAssets};
 use dispatcher::DispatcherState;
+use endscreen::EndScreenState;
 use libplen::constants;
 use libplen::gamestate;
 use libplen::level::{self, Level};
@@ -41,6 +44,9 @@ pub fn send_client_message(msg: &ClientMessage, stream: &mut TcpStream) {
 pub enum StateResult {
     Continue,
     GotoNext,
+    /// Our role in the team changed mid-match, go back through the menu to
+    /// switch views
+    RoleChanged,
     Quit,
 }
 
@@ -86,6 +92,10 @@ pub fn main() -> Result<(), String> {
 
     let ttf_context = sdl2::ttf::init().expect("Could not initialize SDL ttf");
 
+    // Filtering the muffled copies takes a while, so the sounds are only
+    // loaded once
+    let sound_assets = SoundAssets::new();
+
     let mut name = whoami::username();
 
     let mut event_pump = sdl.event_pump().expect("Could not get event pump");
@@ -94,13 +104,17 @@ pub fn main() -> Result<(), String> {
 
     // TODO: only create a window and load assets once
 
+    // The level of the next match, which the server sends while we are
+    // still looking at the end screen
+    let mut next_level: Option<Level> = None;
+
     'mainloop: loop {
         let menu_state = &mut MenuState::new(my_id);
+        menu_state.level = next_level.take();
 
         video_subsystem.text_input().start();
         menu_state.name = name;
 
-        let sound_assets;
         let player_type;
 
         {
@@ -122,7 +136,7 @@ pub fn main() -> Result<(), String> {
             canvas.set_blend_mode(BlendMode::Blend);
             let texture_creator = canvas.texture_creator();
 
-            let assets = Assets::new(&texture_creator, &ttf_context, SoundAssets::new());
+            let assets = Assets::new(&texture_creator, &ttf_context);
 
             'menuloop: loop {
                 let mut current_mouse_click: Option<(i32, i32)> = None;
@@ -172,18 +186,20 @@ pub fn main() -> Result<(), String> {
                     .draw(&mut canvas, &assets, my_player_type)
                     .unwrap();
 
-                if let Some(player) = menu_state.game_state.get_player_by_id(my_id) {
+                if menu_state.should_start() {
+                    let player = menu_state.game_state.get_player_by_id(my_id).unwrap();
                     player_type = player.player_type;
                     break 'menuloop;
                 }
             }
-
-            sound_assets = assets.sounds
         };
 
         video_subsystem.text_input().stop();
 
         name = menu_state.name.clone();
+        // The menu waits for the level before starting the match
+        let level = menu_state.level.clone().unwrap();
+        next_level = Some(level.clone());
 
         match player_type {
             libplen::player::PlayerType::Agent => {
@@ -193,12 +209,13 @@ pub fn main() -> Result<(), String> {
                     &mut reader,
                     &sound_assets,
                     my_id,
+                    level,
                 );
                 sdl = Some(returned_sdl);
 
                 match result {
                     StateResult::Quit => break 'mainloop,
-                    StateResult::Continue => continue,
+                    StateResult::Continue | StateResult::RoleChanged => continue,
                     StateResult::GotoNext => (),
                 }
             }
@@ -222,9 +239,9 @@ pub fn main() -> Result<(), String> {
                     .expect("Could not create canvas");
                 canvas.set_blend_mode(BlendMode::Blend);
                 let texture_creator = canvas.texture_creator();
-                let assets = Assets::new(&texture_creator, &ttf_context, SoundAssets::new());
+                let assets = Assets::new(&texture_creator, &ttf_context);
 
-                let dispatcher_state = &mut DispatcherState::new(my_id);
+                let dispatcher_state = &mut DispatcherState::new(my_id, level);
 
                 let result = 'dispatcher_loop: loop {
                     for event in event_pump.poll_iter() {
@@ -236,10 +253,17 @@ pub fn main() -> Result<(), String> {
                             | Event::Quit { .. } => {
                                 break 'dispatcher_loop StateResult::Quit;
                             }
-                            _ => {}
+                            event => dispatcher_state.handle_event(&event),
                         }
                     }
-                    dispatcher_state.update(&mut reader, &event_pump.keyboard_state());
+                    let state_result = dispatcher_state.update(
+                        &mut reader,
+                        &event_pump.keyboard_state(),
+                        &sound_assets,
+                    );
+                    if state_result != StateResult::Continue {
+                        break 'dispatcher_loop state_result;
+                    }
 
                     rendering::setup_coordinates(&mut canvas)?;
                     canvas.set_draw_color(constants::MENU_BACKGROUND_COLOR);
@@ -254,11 +278,57 @@ pub fn main() -> Result<(), String> {
 
                 match result {
                     StateResult::Quit => break 'mainloop,
-                    StateResult::Continue => continue,
+                    StateResult::Continue | StateResult::RoleChanged => continue,
                     StateResult::GotoNext => (),
                 }
             }
         }
+
+        // The match is over, show the results until the server goes back to
+        // the lobby
+        let window = video_subsystem
+            .window(
+                "MAPP",
+                constants::WINDOW_SIZE as u32,
+                constants::WINDOW_SIZE as u32,
+            )
+            .fullscreen_desktop()
+            .resizable()
+            .build()
+            .expect("Could not create window");
+
+        let mut canvas = window
+            .into_canvas()
+            .build()
+            .expect("Could not create canvas");
+        canvas.set_blend_mode(BlendMode::Blend);
+        let texture_creator = canvas.texture_creator();
+        let assets = Assets::new(&texture_creator, &ttf_context);
+
+        let end_screen_state = &mut EndScreenState::new();
+
+        loop {
+            for event in event_pump.poll_iter() {
+                if let Event::Quit { .. } = event {
+                    break 'mainloop;
+                }
+            }
+
+            if end_screen_state.update(&mut reader) == StateResult::GotoNext {
+                if let Some(level) = end_screen_state.level.take() {
+                    next_level = Some(level);
+                }
+                break;
+            }
+
+            rendering::setup_coordinates(&mut canvas)?;
+            canvas.set_draw_color(constants::MENU_BACKGROUND_COLOR);
+            canvas.clear();
+
+            end_screen_state.draw(&mut canvas, &assets)?;
+
+            canvas.present();
+        }
     }
 
     Ok(())
@@ -1,29 +1,57 @@
 use std::time::Instant;
 
+use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-use libplen::level::{self, Level};
+use libplen::door::DoorState;
+use libplen::level::Level;
+use libplen::marker::{MarkerKind, MarkerList};
+use libplen::math::{vec2, Vec2};
 use libplen::messages::{ClientInput, ClientMessage, MessageReader, ServerMessage};
-use libplen::player;
 
-use crate::{assets::Assets, gamestate, map, StateResult};
+use crate::assets::{Assets, SoundAssets};
+use crate::audio::{self, Listener};
+use crate::{gamestate, map, rendering, StateResult};
+
+const MARKER_SCALE: f32 = 0.4;
 
 pub struct DispatcherState {
     my_id: u64,
     game_state: gamestate::GameState,
     map: map::Map,
     last_time: Instant,
+    markers: MarkerList,
+    /// The kind of marker that right clicking on the map places
+    marker_kind: MarkerKind,
+    /// Screen positions where new markers should be placed
+    marker_clicks: Vec<Vec2>,
 }
 
 impl DispatcherState {
-    pub fn new(my_id: u64) -> DispatcherState {
+    pub fn new(my_id: u64, level: Level) -> DispatcherState {
         DispatcherState {
             my_id,
             game_state: gamestate::GameState::new(),
-            map: map::Map::new(level::example_level()),
+            map: map::Map::new(level),
             last_time: Instant::now(),
+            markers: MarkerList::new(),
+            marker_kind: MarkerKind::Waypoint,
+            marker_clicks: vec![],
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Right,
+                x,
+                y,
+                ..
+            } => self.marker_clicks.push(vec2(x as f32, y as f32)),
+            _ => self.map.handle_event(event),
         }
     }
 
@@ -31,6 +59,7 @@ impl DispatcherState {
         &mut self,
         server_reader: &mut MessageReader,
         keyboard_state: &sdl2::keyboard::KeyboardState,
+        sounds: &SoundAssets,
     ) -> StateResult {
         let elapsed = self.last_time.elapsed();
         self.last_time = Instant::now();
@@ -47,7 +76,20 @@ impl DispatcherState {
                     panic!("Got new ID after intialisation")
                 }
                 ServerMessage::GameState(state) => self.game_state = state,
-                ServerMessage::PlaySound(_sound, _pos) => {}
+                // Dispatchers hear everything as if standing in the middle
+                // of their view of the map, with north as straight ahead
+                ServerMessage::PlaySound(sound, position) => {
+                    let listener = Listener {
+                        position: self.map.center,
+                        rotation: 0.,
+                    };
+                    audio::play_at(sounds, sound, position, &listener, false);
+                }
+                // Levels only change between matches
+                ServerMessage::Level(_) => {}
+                ServerMessage::Marker(marker) => self.markers.add(marker),
+                // Teams are only joined from the menu
+                ServerMessage::JoinRejected(_) => {}
             }
         }
 
@@ -66,24 +108,160 @@ impl DispatcherState {
             input.x_input += 1.0;
         }
 
+        if keyboard_state.is_scancode_pressed(Scancode::Num1) {
+            self.marker_kind = MarkerKind::Waypoint;
+        }
+        if keyboard_state.is_scancode_pressed(Scancode::Num2) {
+            self.marker_kind = MarkerKind::Danger;
+        }
+        if keyboard_state.is_scancode_pressed(Scancode::Num3) {
+            self.marker_kind = MarkerKind::GoHere;
+        }
+
+        // Clicking a door opens or closes it, shift clicking locks or
+        // unlocks it
+        let lock = keyboard_state.is_scancode_pressed(Scancode::LShift)
+            || keyboard_state.is_scancode_pressed(Scancode::RShift);
+        for (a, b) in self.map.take_door_clicks() {
+            let door = match self.map.door_between(a, b) {
+                Some(door) => door,
+                None => continue,
+            };
+            let state = match (door.state, lock) {
+                (DoorState::Open, false) => DoorState::Closed,
+                (DoorState::Closed, false) | (DoorState::Locked, false) => DoorState::Open,
+                (DoorState::Locked, true) => DoorState::Closed,
+                (_, true) => DoorState::Locked,
+            };
+            let message = ClientMessage::SetDoor {
+                room: door.room,
+                direction: door.direction,
+                state,
+            };
+            crate::send_client_message(&message, &mut server_reader.stream);
+        }
+
+        for click in self.marker_clicks.drain(..) {
+            let message = ClientMessage::PlaceMarker {
+                kind: self.marker_kind,
+                position: self.map.transform().screen_to_world(click),
+            };
+            crate::send_client_message(&message, &mut server_reader.stream);
+        }
+
+        self.markers.update(elapsed.as_secs_f32());
         self.map
             .update(elapsed.as_secs_f32(), &self.game_state, self.my_id);
 
         let input_message = ClientMessage::Input(input);
         crate::send_client_message(&input_message, &mut server_reader.stream);
 
-        StateResult::Continue
+        if self.game_state.is_finished() {
+            StateResult::GotoNext
+        } else {
+            StateResult::Continue
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        self.map.draw(canvas, assets)?;
+        self.draw_markers(canvas, assets)?;
+        self.draw_scoreboard(canvas, assets)?;
+        self.draw_killfeed(canvas, assets)?;
+        self.draw_help(canvas, assets)?;
+
+        Ok(())
     }
 
-    fn _myself(&self) -> &player::Player {
-        let Self {
-            my_id, game_state, ..
-        } = self;
-        game_state.get_player_by_id(*my_id).unwrap()
+    fn draw_markers(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        let transform = self.map.transform();
+        for marker in self.markers.iter() {
+            let position = transform.world_to_screen(marker.position);
+            let texture = assets.marker(marker.kind);
+            rendering::draw_texture_rotated_and_scaled(
+                canvas,
+                texture,
+                position,
+                0.,
+                vec2(MARKER_SCALE, MARKER_SCALE),
+            )?;
+        }
+        Ok(())
     }
 
-    pub fn draw(&mut self, canvas: &mut Canvas<Window>, _assets: &Assets) -> Result<(), String> {
-        self.map.draw(canvas)?;
+    fn draw_help(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        let texture_creator = canvas.texture_creator();
+        let (_, screen_h) = canvas.logical_size();
+
+        let lines = [
+            (
+                String::from("Doors: click to open or close, shift click to lock"),
+                (255, 255, 255),
+            ),
+            (
+                format!(
+                    "Marker: {} (1-3 to change, right click to place)",
+                    self.marker_kind.name()
+                ),
+                self.marker_kind.color(),
+            ),
+        ];
+        for (i, (line, color)) in lines.iter().enumerate() {
+            let text = assets
+                .font
+                .render(line)
+                .blended(*color)
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+            let y = screen_h as f32 - 30. * (lines.len() - i) as f32;
+            rendering::draw_texture(canvas, &text_texture, vec2(10., y))?;
+        }
+        Ok(())
+    }
+
+    fn draw_killfeed(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        let texture_creator = canvas.texture_creator();
+        let (screen_w, _) = canvas.logical_size();
+
+        let mut y = 10.;
+        for message in self.game_state.killfeed.get_messages() {
+            let text = assets
+                .font
+                .render(&message.message)
+                .blended((255, 255, 255))
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+
+            let query = text_texture.query();
+            let position = vec2(screen_w as f32 - query.width as f32 - 10., y);
+            rendering::draw_texture(canvas, &text_texture, position)?;
+            y += query.height as f32 + 4.;
+        }
+        Ok(())
+    }
+
+    fn draw_scoreboard(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        let texture_creator = canvas.texture_creator();
+        let mut x = 10.;
+        for team in self.game_state.sorted_teams() {
+            let text = assets
+                .font
+                .render(&format!("{} {}", team.name, team.score))
+                .blended(team.color)
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+
+            rendering::draw_texture(canvas, &text_texture, vec2(x, 10.))?;
+            x += text_texture.query().width as f32 + 20.;
+        }
+
+        let text = assets
+            .font
+            .render(&self.game_state.match_state.clock_text())
+            .blended((255, 255, 255))
+            .expect("Could not render text");
+        let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+        rendering::draw_texture(canvas, &text_texture, vec2(x, 10.))?;
 
         Ok(())
     }
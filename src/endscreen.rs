@@ -0,0 +1,83 @@
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use libplen::gamestate::{GameState, MatchState};
+use libplen::level::Level;
+use libplen::math::vec2;
+use libplen::messages::{MessageReader, ServerMessage};
+
+use crate::assets::Assets;
+use crate::{rendering, StateResult};
+
+pub struct EndScreenState {
+    /// None until the server has sent us the final state of the match
+    game_state: Option<GameState>,
+    /// The level of the next match, if the server has changed it
+    pub level: Option<Level>,
+}
+
+impl EndScreenState {
+    pub fn new() -> EndScreenState {
+        EndScreenState {
+            game_state: None,
+            level: None,
+        }
+    }
+
+    pub fn update(&mut self, server_reader: &mut MessageReader) -> StateResult {
+        server_reader.fetch_bytes().unwrap();
+        for message in server_reader.iter() {
+            match bincode::deserialize(&message).unwrap() {
+                ServerMessage::GameState(state) => self.game_state = Some(state),
+                ServerMessage::Level(level) => self.level = Some(level),
+                _ => {}
+            }
+        }
+
+        match &self.game_state {
+            Some(state) if state.match_state == MatchState::Lobby => StateResult::GotoNext,
+            _ => StateResult::Continue,
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        canvas.copy(&assets.end_background, None, None)?;
+
+        let game_state = match &self.game_state {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        let headline = match game_state.match_state {
+            MatchState::Finished {
+                winner: Some(team_id),
+                ..
+            } => format!("Team {} wins!", game_state.teams[&team_id].name),
+            _ => String::from("It's a draw!"),
+        };
+
+        let res_offset = rendering::calculate_resolution_offset(canvas);
+        let texture_creator = canvas.texture_creator();
+
+        let text = assets
+            .font
+            .render(&headline)
+            .blended((255, 255, 255))
+            .expect("Could not render text");
+        let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+        rendering::draw_texture(canvas, &text_texture, vec2(50., 100.) + res_offset)?;
+
+        for (i, team) in game_state.sorted_teams().iter().enumerate() {
+            let text = assets
+                .font
+                .render(&format!("{}: {}", team.name, team.score))
+                .blended(team.color)
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+            let pos = vec2(50., 150. + 30. * i as f32);
+            rendering::draw_texture(canvas, &text_texture, pos + res_offset)?;
+        }
+
+        Ok(())
+    }
+}
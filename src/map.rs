@@ -1,73 +1,472 @@
+use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
+use ultraviolet::Rotor2;
 
-use libplen::constants::{self, DOORWAY_LENGTH, ROOM_LENGTH, ROOM_WIDTH, SCREEN_PADDING};
+use libplen::constants::{self, DOORWAY_LENGTH, PLAYER_RADIUS, ROOM_WIDTH, SCREEN_PADDING};
+use libplen::door::{Door, DoorState};
 use libplen::gamestate::GameState;
-use libplen::level::{self, Level, Room};
-use libplen::math::vec2;
+use libplen::level::{self, Level, Location, Room, RoomCoord, RoomGraph};
+use libplen::math::{self, vec2, Vec2};
+use libplen::player::Player;
+use libplen::weapon::WeaponKind;
+
+use crate::assets::Assets;
+use crate::rendering;
+
+/// Length of the lines showing what a player is looking at, in world units
+const VIEW_LINE_LENGTH: f32 = 1.5;
+/// Half of the field of view of agents
+const VIEW_ANGLE: f32 = std::f32::consts::PI / 6.;
+const FLAG_SIZE: f32 = 0.4;
+/// Props smaller than this are drawn bigger so that they can be made out
+const MIN_PROP_ICON_SIZE: f32 = 0.5;
+/// Height of the hack progress bars below server racks, in pixels
+const HACK_BAR_HEIGHT: u32 = 4;
+
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 6.;
+/// How much one step of the mouse wheel zooms
+const ZOOM_STEP: f32 = 1.2;
+/// Clicks that move the mouse further than this many pixels pan the map
+/// instead of selecting a room or a door
+const DRAG_THRESHOLD: f32 = 4.;
+
+const DETAILS_POS: (f32, f32) = (10., 40.);
+const DETAILS_LINE_HEIGHT: f32 = 20.;
+
+/// Width of the level including some padding on either side
+fn map_width() -> f32 {
+    ROOM_WIDTH * 8. + DOORWAY_LENGTH * 7. + SCREEN_PADDING * 2.
+}
+
+/// Conversion between world coordinates and screen pixels
+#[derive(Clone, Copy, Debug)]
+pub struct MapTransform {
+    /// Pixels per world unit
+    pub scale: f32,
+    /// Screen position of the world origin
+    pub offset: Vec2,
+}
+
+impl MapTransform {
+    pub fn world_to_screen(&self, position: Vec2) -> Vec2 {
+        position * self.scale + self.offset
+    }
+
+    pub fn screen_to_world(&self, position: Vec2) -> Vec2 {
+        (position - self.offset) / self.scale
+    }
+
+    pub fn rect(&self, rect: &math::Rect) -> sdl2::rect::Rect {
+        let min = self.world_to_screen(rect.min);
+        let size = rect.size() * self.scale;
+        sdl2::rect::Rect::new(min.x as i32, min.y as i32, size.x as u32, size.y as u32)
+    }
+
+    fn point(&self, position: Vec2) -> (i32, i32) {
+        let position = self.world_to_screen(position);
+        (position.x as i32, position.y as i32)
+    }
+}
 
 pub struct Map {
     pub level: Level,
+    pub graph: RoomGraph,
+    /// Logical size of the canvas that the map was last drawn on
+    screen_size: (u32, u32),
+    /// The latest state from the server, which only contains the enemies
+    /// that our team can see
+    game_state: GameState,
+    /// 1 fits the whole level in the width of the screen
+    zoom: f32,
+    /// The world position in the middle of the screen
+    pub center: Vec2,
+    mouse_position: Vec2,
+    /// Where the left mouse button was pressed, while it is held down
+    drag_start: Option<Vec2>,
+    selected_room: Option<RoomCoord>,
+    /// Doorways that have been clicked on since the last call to
+    /// `take_door_clicks`
+    door_clicks: Vec<(RoomCoord, RoomCoord)>,
 }
 
 impl Map {
     pub fn new(level: Level) -> Map {
-        Map { level }
+        Map {
+            graph: RoomGraph::new(&level),
+            level,
+            screen_size: (constants::WINDOW_SIZE as u32, constants::WINDOW_SIZE as u32),
+            game_state: GameState::new(),
+            zoom: 1.,
+            center: vec2(map_width() / 2. - SCREEN_PADDING, 0.),
+            mouse_position: vec2(0., 0.),
+            drag_start: None,
+            selected_room: None,
+            door_clicks: vec![],
+        }
     }
 
-    pub fn update(&mut self, _delta_time: f32, _game_state: &GameState, _my_id: u64) {
-        // update client side stuff
+    pub fn transform(&self) -> MapTransform {
+        let (screen_w, screen_h) = self.screen_size;
+        let screen_center = vec2(screen_w as f32 * 0.5, screen_h as f32 * 0.5);
+        let scale = screen_w as f32 / map_width() * self.zoom;
+        MapTransform {
+            scale,
+            offset: screen_center - self.center * scale,
+        }
     }
 
-    pub fn draw(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
-        let (screen_w, screen_h) = canvas.logical_size();
-        let screen_center = vec2(screen_w as f32 * 0.5, screen_h as f32 * 0.5);
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => self.drag_start = Some(vec2(x as f32, y as f32)),
+            Event::MouseButtonUp {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => {
+                let position = vec2(x as f32, y as f32);
+                if let Some(start) = self.drag_start.take() {
+                    if (position - start).mag() < DRAG_THRESHOLD {
+                        self.select_at(position);
+                    }
+                }
+            }
+            Event::MouseMotion {
+                x,
+                y,
+                xrel,
+                yrel,
+                ..
+            } => {
+                self.mouse_position = vec2(x as f32, y as f32);
+                if self.drag_start.is_some() {
+                    self.center -= vec2(xrel as f32, yrel as f32) / self.transform().scale;
+                }
+            }
+            Event::MouseWheel { y, .. } => {
+                self.zoom_at(self.mouse_position, ZOOM_STEP.powi(y));
+            }
+            _ => {}
+        }
+    }
+
+    /// Zooms while keeping the world position under `screen_position` in
+    /// the same place on the screen
+    fn zoom_at(&mut self, screen_position: Vec2, factor: f32) {
+        let before = self.transform().screen_to_world(screen_position);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let after = self.transform().screen_to_world(screen_position);
+        self.center += before - after;
+    }
+
+    fn select_at(&mut self, screen_position: Vec2) {
+        let position = self.transform().screen_to_world(screen_position);
+        self.selected_room = match self.graph.locate(position) {
+            Some(Location::Room(room)) => Some(room),
+            Some(Location::Doorway(a, b)) => {
+                self.door_clicks.push((a, b));
+                self.selected_room
+            }
+            None => None,
+        };
+    }
+
+    pub fn take_door_clicks(&mut self) -> Vec<(RoomCoord, RoomCoord)> {
+        std::mem::take(&mut self.door_clicks)
+    }
+
+    /// The door between two neighbouring rooms, as of the latest state
+    pub fn door_between(&self, a: RoomCoord, b: RoomCoord) -> Option<&Door> {
+        self.game_state.doors.iter().find(|door| door.connects(a, b))
+    }
+
+    pub fn update(&mut self, _delta_time: f32, game_state: &GameState, _my_id: u64) {
+        self.game_state = game_state.clone();
+    }
 
-        let map_width = ROOM_WIDTH * 8. + DOORWAY_LENGTH * 7. + SCREEN_PADDING * 2.;
-        let scale = screen_w as f32 / map_width;
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        self.screen_size = canvas.logical_size();
+        let transform = self.transform();
 
         for col in 0..8 {
             let rooms_in_column = level::rooms_in_col(col);
             for row in 0..rooms_in_column {
                 match &self.level.rooms[col][row] {
                     Room::FullRoom(doors) => {
-                        let room_pos = level::room_corner_position(col, row);
-                        let dest_rect = sdl2::rect::Rect::new(
-                            ((room_pos.x + SCREEN_PADDING) * scale) as i32,
-                            (screen_center.y + room_pos.y * scale) as i32,
-                            (ROOM_WIDTH * scale) as u32,
-                            (ROOM_LENGTH * scale) as u32,
-                        );
-
-                        canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
+                        let dest_rect = transform.rect(&level::room_bounds(col, row));
+
+                        canvas.set_draw_color(Color::RGB(255, 255, 255));
                         canvas.fill_rect(dest_rect)?;
 
-                        self.draw_doors(canvas, doors, (col, row), scale)?;
+                        self.draw_doors(canvas, doors, (col, row))?;
                     }
                     Room::Corridor(doors) => {
-                        let room_pos = level::room_corner_position(col, row);
-                        let room_center = room_pos + vec2(ROOM_WIDTH / 2., ROOM_LENGTH / 2.);
-                        let rc_x = ((room_center.x + SCREEN_PADDING) * scale) as i32;
-                        let rc_y = (screen_center.y + room_center.y * scale) as i32;
+                        canvas.set_draw_color(Color::RGB(200, 200, 200));
+                        for area in level::corridor_areas((col, row), doors) {
+                            canvas.fill_rect(transform.rect(&area))?;
+                        }
 
-                        canvas.set_draw_color(sdl2::pixels::Color::RGB(200, 200, 200));
-                        for door in doors {
-                            let door_offset = level::doorway_transform((col, row), *door).1;
-                            let door_pos = room_center + door_offset;
+                        self.draw_doors(canvas, doors, (col, row))?;
+                    }
+                    Room::Empty => {}
+                }
+            }
+        }
 
-                            let door_x = ((door_pos.x + SCREEN_PADDING) * scale) as i32;
-                            let door_y = (screen_center.y + door_pos.y * scale) as i32;
+        if let Some((col, row)) = self.selected_room {
+            canvas.set_draw_color(Color::RGB(255, 210, 0));
+            canvas.draw_rect(transform.rect(&level::room_bounds(col, row)))?;
+        }
 
-                            canvas.draw_line((rc_x, rc_y), (door_x, door_y))?;
-                        }
+        self.draw_props(canvas, assets)?;
+        self.draw_flags(canvas)?;
+        self.draw_shots(canvas)?;
+        self.draw_players(canvas, assets)?;
+        self.draw_room_details(canvas, assets)?;
+
+        Ok(())
+    }
 
-                        self.draw_doors(canvas, doors, (col, row), scale)?;
+    fn draw_props(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        let transform = self.transform();
+        for prop in self.game_state.visible_props() {
+            let texture = assets.prop(prop.kind);
+            let size = (prop.kind.radius() * 2.).max(MIN_PROP_ICON_SIZE) * transform.scale;
+            let scale = size / texture.query().width as f32;
+            let position = transform.world_to_screen(prop.position);
+            rendering::draw_texture_rotated_and_scaled(
+                canvas,
+                texture,
+                position,
+                0.,
+                vec2(scale, scale),
+            )?;
+
+            // Racks are outlined with the colour of their team, and show how
+            // far along hacking them is
+            let team_color = |team_id| self.game_state.teams.get(&team_id).map(|team| team.color);
+            if let Some((r, g, b)) = prop.team.and_then(team_color) {
+                let corner = position - vec2(size, size) / 2.;
+                canvas.set_draw_color(Color::RGB(r, g, b));
+                canvas.draw_rect(sdl2::rect::Rect::new(
+                    corner.x as i32,
+                    corner.y as i32,
+                    size as u32,
+                    size as u32,
+                ))?;
+            }
+            if prop.hack_progress > 0. {
+                let corner = position + vec2(-size / 2., size / 2. + 2.);
+                canvas.set_draw_color(Color::RGB(0, 0, 0));
+                canvas.fill_rect(sdl2::rect::Rect::new(
+                    corner.x as i32,
+                    corner.y as i32,
+                    size as u32,
+                    HACK_BAR_HEIGHT,
+                ))?;
+                let (r, g, b) = prop.hacked_by.and_then(team_color).unwrap_or((255, 255, 255));
+                canvas.set_draw_color(Color::RGB(r, g, b));
+                canvas.fill_rect(sdl2::rect::Rect::new(
+                    corner.x as i32,
+                    corner.y as i32,
+                    ((size * prop.hack_progress) as u32).max(1),
+                    HACK_BAR_HEIGHT,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_flags(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let transform = self.transform();
+        let size = (FLAG_SIZE * transform.scale) as i32;
+
+        for flag in self.game_state.visible_flags() {
+            let (r, g, b) = self.game_state.teams[&flag.team_id].color;
+            let (x, y) = transform.point(flag.position);
+
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            canvas.draw_line((x, y), (x, y - size))?;
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas.fill_rect(sdl2::rect::Rect::new(
+                x + 1,
+                y - size,
+                size as u32,
+                (size / 2) as u32,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn draw_shots(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let transform = self.transform();
+
+        for shot in &self.game_state.shots {
+            let (r, g, b) = self
+                .game_state
+                .team_of_player(shot.shooter)
+                .map(|team_id| self.game_state.teams[&team_id].color)
+                .unwrap_or((255, 255, 255));
+            canvas.set_draw_color(Color::RGB(r, g, b));
+
+            // Lasers are drawn as thicker beams
+            let width: i32 = match shot.kind {
+                WeaponKind::Gun => 0,
+                WeaponKind::Laser => 1,
+            };
+            let (from, to) = (transform.point(shot.from), transform.point(shot.to));
+            for dx in -width..=width {
+                for dy in -width..=width {
+                    canvas.draw_line((from.0 + dx, from.1 + dy), (to.0 + dx, to.1 + dy))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_players(&self, canvas: &mut Canvas<Window>, assets: &Assets) -> Result<(), String> {
+        for team in self.game_state.sorted_teams() {
+            for agent in team.agents.iter().filter(|agent| agent.visible && agent.is_alive()) {
+                self.draw_player(canvas, assets, agent, team.color)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_player(
+        &self,
+        canvas: &mut Canvas<Window>,
+        assets: &Assets,
+        player: &Player,
+        (r, g, b): (u8, u8, u8),
+    ) -> Result<(), String> {
+        let transform = self.transform();
+        let center = transform.world_to_screen(player.position);
+        let radius = (PLAYER_RADIUS * transform.scale).max(3.);
+
+        // Lines along the edges and middle of the field of view
+        canvas.set_draw_color(Color::RGBA(r, g, b, 150));
+        for &angle in &[-VIEW_ANGLE, 0., VIEW_ANGLE] {
+            let direction = vec2(0., -1.).rotated_by(Rotor2::from_angle(angle - player.rotation));
+            canvas.draw_line(
+                transform.point(player.position),
+                transform.point(player.position + direction * VIEW_LINE_LENGTH),
+            )?;
+        }
+
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(sdl2::rect::Rect::new(
+            (center.x - radius) as i32,
+            (center.y - radius) as i32,
+            (radius * 2.) as u32,
+            (radius * 2.) as u32,
+        ))?;
+
+        let texture_creator = canvas.texture_creator();
+        let text = assets
+            .font
+            .render(&player.name)
+            .blended((r, g, b))
+            .expect("Could not render text");
+        let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+        let label_position = center - vec2(0., radius + text_texture.query().height as f32);
+        rendering::draw_texture_centered(canvas, &text_texture, label_position)
+    }
+
+    /// Lines of text describing the selected room, along with their colour
+    fn room_details(&self, room: RoomCoord) -> Vec<(String, (u8, u8, u8))> {
+        let white = (255, 255, 255);
+        let kind = match self.level.room(room) {
+            Some(Room::FullRoom(_)) => "Room",
+            Some(Room::Corridor(_)) => "Corridor",
+            Some(Room::Empty) | None => return vec![],
+        };
+        let mut lines = vec![(format!("{} {:?}", kind, room), white)];
+
+        let doors = self.graph.neighbours(room);
+        if doors.is_empty() {
+            lines.push((String::from("No doors"), white));
+        } else {
+            let targets = doors
+                .iter()
+                .map(|&target| match self.door_between(room, target) {
+                    Some(door) if !door.is_open() => {
+                        format!("{:?} ({})", target, door.state.name())
                     }
-                    Room::Empty => {}
+                    _ => format!("{:?}", target),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push((format!("Doors to {}", targets), white));
+        }
+
+        let bounds = level::room_bounds(room.0, room.1);
+        let props = self
+            .game_state
+            .visible_props()
+            .filter(|prop| prop.carrier.is_none() && bounds.contains(prop.position))
+            .map(|prop| match prop.hacked_by {
+                Some(_) => format!("{} (hacked)", prop.kind.name()),
+                None if prop.hack_progress > 0. => {
+                    format!("{} ({:.0}% hacked)", prop.kind.name(), prop.hack_progress * 100.)
+                }
+                None => String::from(prop.kind.name()),
+            })
+            .collect::<Vec<_>>();
+        if !props.is_empty() {
+            lines.push((format!("Props: {}", props.join(", ")), white));
+        }
+
+        let header_index = lines.len();
+        let mut anyone_inside = false;
+        for team in self.game_state.sorted_teams() {
+            for agent in team.agents.iter().filter(|agent| agent.visible && agent.is_alive()) {
+                if self.graph.locate(agent.position) == Some(Location::Room(room)) {
+                    lines.push((format!("  {}", agent.name), team.color));
+                    anyone_inside = true;
                 }
             }
         }
+        let inside_header = if anyone_inside {
+            "Inside:"
+        } else {
+            "Nobody inside that we know of"
+        };
+        lines.insert(header_index, (String::from(inside_header), white));
+
+        lines
+    }
+
+    fn draw_room_details(
+        &self,
+        canvas: &mut Canvas<Window>,
+        assets: &Assets,
+    ) -> Result<(), String> {
+        let room = match self.selected_room {
+            Some(room) => room,
+            None => return Ok(()),
+        };
 
+        let texture_creator = canvas.texture_creator();
+        for (i, (line, color)) in self.room_details(room).into_iter().enumerate() {
+            let text = assets
+                .font
+                .render(&line)
+                .blended(color)
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+            let pos = vec2(DETAILS_POS.0, DETAILS_POS.1 + i as f32 * DETAILS_LINE_HEIGHT);
+            rendering::draw_texture(canvas, &text_texture, pos)?;
+        }
         Ok(())
     }
 
@@ -76,29 +475,26 @@ impl Map {
         canvas: &mut Canvas<Window>,
         doors: &[(i8, i8)],
         grid_pos: (usize, usize),
-        scale: f32,
     ) -> Result<(), String> {
-        let (screen_w, screen_h) = canvas.logical_size();
-        let screen_center = vec2(screen_w as f32 * 0.5, screen_h as f32 * 0.5);
+        let transform = self.transform();
+
+        for &door in doors {
+            let (door_corner1, door_corner2) = level::doorway_bounds(grid_pos, door);
+            let dest_rect = transform.rect(&math::Rect::from_corners(door_corner1, door_corner2));
 
-        for door in doors {
-            let (door_corner1, door_corner2) = level::doorway_bounds(grid_pos, *door);
-            let door_pos = vec2(
-                door_corner1.x.min(door_corner2.x),
-                door_corner1.y.min(door_corner2.y),
-            );
-            let door_size = vec2(
-                (door_corner1.x - door_corner2.x).abs(),
-                (door_corner1.y - door_corner2.y).abs(),
-            );
-            let dest_rect = sdl2::rect::Rect::new(
-                ((door_pos.x + SCREEN_PADDING) * scale) as i32,
-                (screen_center.y + door_pos.y * scale) as i32,
-                (door_size.x * scale) as u32,
-                (door_size.y * scale) as u32,
-            );
-
-            canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 0, 0));
+            let state = level::door_target(grid_pos, door)
+                .and_then(|target| self.door_between(grid_pos, target))
+                .map(|door| (door.state, door.owner));
+            // Locked doors show who holds the key
+            let (r, g, b) = match state {
+                Some((DoorState::Closed, _)) => (90, 90, 90),
+                Some((DoorState::Locked, owner)) => owner
+                    .and_then(|owner| self.game_state.teams.get(&owner))
+                    .map(|team| team.color)
+                    .unwrap_or((0, 0, 0)),
+                Some((DoorState::Open, _)) | None => (230, 230, 230),
+            };
+            canvas.set_draw_color(Color::RGB(r, g, b));
             canvas.fill_rect(dest_rect)?;
         }
         Ok(())
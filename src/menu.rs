@@ -7,14 +7,20 @@ use crate::assets::Assets;
 use crate::rendering;
 
 use libplen::constants;
-use libplen::gamestate::GameState;
+use libplen::gamestate::{GameState, MatchState};
+use libplen::level::Level;
 use libplen::math::{vec2, Vec2};
 use libplen::messages::{ClientMessage, MessageReader, ServerMessage};
-use libplen::player::{Player, PlayerType};
+use libplen::player::{JoinError, PlayerType};
 
 pub enum ButtonAction {
     SetAgent(u64), // team id
     SetDispatcher(u64),
+    /// Wait in line for the dispatcher of the team to leave
+    QueueDispatcher(u64),
+    /// Join the team with the fewest players as an agent
+    AutoAssign,
+    ToggleReady,
 }
 
 pub struct Button {
@@ -28,11 +34,14 @@ pub struct Button {
 
 pub struct MenuState {
     pub name: String,
-    pub player_type: PlayerType,
     pub game_state: GameState,
-    pub team_id: u64,
     pub my_id: u64,
     pub buttons: Vec<Button>,
+    /// The level that the next match will be played on, once the server
+    /// has sent it
+    pub level: Option<Level>,
+    /// Why the server turned down the last request to join a team
+    pub rejection: Option<JoinError>,
 }
 
 impl MenuState {
@@ -40,60 +49,77 @@ impl MenuState {
         let mut menu = MenuState {
             name: String::new(),
             game_state: GameState::new(),
-            player_type: PlayerType::Agent,
-            team_id: 0,
             buttons: vec![],
             my_id,
+            level: None,
+            rejection: None,
         };
         menu.build_menu_buttons();
         menu
     }
 
+    /// A column of join buttons for every team, in the colour of the team,
+    /// and the auto-assign and ready buttons below them
     pub fn build_menu_buttons(&mut self) {
-        let red_disp_pos = vec2(1. / 4., constants::MENU_BUTTON_JOIN_DISPATCHER_Y);
-        let blue_disp_pos = vec2(3. / 4., constants::MENU_BUTTON_JOIN_DISPATCHER_Y);
-        let red_ag_pos = vec2(1. / 4., constants::MENU_BUTTON_JOIN_AGENT_Y);
-        let blue_ag_pos = vec2(3. / 4., constants::MENU_BUTTON_JOIN_AGENT_Y);
+        self.buttons.clear();
+
+        let teams = self.game_state.sorted_teams();
+        for (i, team) in teams.iter().enumerate() {
+            let x = (i as f32 + 0.5) / teams.len() as f32;
+            let color = team_shade(team.color, constants::MENU_BUTTON_BRIGHTNESS);
+
+            let (text, action) = if team.has_other_dispatcher(self.my_id) {
+                ("Queue as Dispatcher", ButtonAction::QueueDispatcher(team.id))
+            } else {
+                ("Join as Dispatcher", ButtonAction::SetDispatcher(team.id))
+            };
+            self.buttons.push(Button {
+                pos: vec2(x, constants::MENU_BUTTON_JOIN_DISPATCHER_Y),
+                h: constants::MENU_BUTTON_HEIGHT,
+                w: constants::MENU_BUTTON_WIDTH,
+                text: String::from(text),
+                color,
+                action,
+            });
+            self.buttons.push(Button {
+                pos: vec2(x, constants::MENU_BUTTON_JOIN_AGENT_Y),
+                h: constants::MENU_BUTTON_HEIGHT,
+                w: constants::MENU_BUTTON_WIDTH,
+                text: String::from("Join as Agent"),
+                color,
+                action: ButtonAction::SetAgent(team.id),
+            });
+        }
 
-        let red_disp_btn = Button {
-            pos: red_disp_pos,
+        let (auto_x, auto_y) = constants::MENU_BUTTON_AUTO_ASSIGN_POS;
+        self.buttons.push(Button {
+            pos: vec2(auto_x, auto_y),
             h: constants::MENU_BUTTON_HEIGHT,
             w: constants::MENU_BUTTON_WIDTH,
-            text: String::from("Join as Dispatcher"),
-            color: constants::MENU_RED_BUTTON_COLOR.into(),
-            action: ButtonAction::SetDispatcher(constants::TEAM_RED_ID),
-        };
-
-        let blue_disp_btn = Button {
-            pos: blue_disp_pos,
+            text: String::from("Auto-assign"),
+            color: constants::MENU_AUTO_ASSIGN_BUTTON_COLOR.into(),
+            action: ButtonAction::AutoAssign,
+        });
+
+        let (ready_x, ready_y) = constants::MENU_BUTTON_READY_POS;
+        self.buttons.push(Button {
+            pos: vec2(ready_x, ready_y),
             h: constants::MENU_BUTTON_HEIGHT,
             w: constants::MENU_BUTTON_WIDTH,
-            text: String::from("Join as Dispatcher"),
-            color: constants::MENU_BLUE_BUTTON_COLOR.into(),
-            action: ButtonAction::SetDispatcher(constants::TEAM_BLUE_ID),
-        };
+            text: String::from("Ready"),
+            color: constants::MENU_READY_BUTTON_COLOR.into(),
+            action: ButtonAction::ToggleReady,
+        });
+    }
 
-        let red_agent_btn = Button {
-            pos: red_ag_pos,
-            h: constants::MENU_BUTTON_HEIGHT,
-            w: constants::MENU_BUTTON_WIDTH,
-            text: String::from("Join as Agent"),
-            color: constants::MENU_RED_BUTTON_COLOR.into(),
-            action: ButtonAction::SetAgent(constants::TEAM_RED_ID),
+    /// Whether we have joined a team and the server has started a match
+    pub fn should_start(&self) -> bool {
+        let in_team = self.game_state.get_player_by_id(self.my_id).is_some();
+        let match_started = match self.game_state.match_state {
+            MatchState::Countdown { .. } | MatchState::InProgress { .. } => true,
+            MatchState::Lobby | MatchState::Finished { .. } => false,
         };
-
-        let blue_agent_btn = Button {
-            pos: blue_ag_pos,
-            h: constants::MENU_BUTTON_HEIGHT,
-            w: constants::MENU_BUTTON_WIDTH,
-            text: String::from("Join as Agent"),
-            color: constants::MENU_BLUE_BUTTON_COLOR.into(),
-            action: ButtonAction::SetAgent(constants::TEAM_BLUE_ID),
-        };
-        self.buttons.push(red_disp_btn);
-        self.buttons.push(blue_disp_btn);
-        self.buttons.push(red_agent_btn);
-        self.buttons.push(blue_agent_btn);
+        in_team && match_started && self.level.is_some()
     }
 
     fn draw_player_name(
@@ -126,11 +152,24 @@ impl MenuState {
         server_reader.fetch_bytes().unwrap();
         for message in server_reader.iter() {
             match bincode::deserialize(&message).unwrap() {
-                ServerMessage::GameState(state) => self.game_state = state,
+                ServerMessage::GameState(state) => {
+                    // The buttons depend on the teams and who has joined them
+                    self.game_state = state;
+                    self.build_menu_buttons();
+                }
+                ServerMessage::Level(level) => self.level = Some(level),
+                ServerMessage::JoinRejected(error) => self.rejection = Some(error),
                 _ => {}
             }
         }
         self.check_buttons(current_mouse_click, &mut messages_to_send, window_size);
+
+        let joining = messages_to_send
+            .iter()
+            .any(|message| matches!(message, ClientMessage::JoinTeam { .. }));
+        if joining {
+            self.rejection = None;
+        }
         messages_to_send
     }
 
@@ -142,18 +181,41 @@ impl MenuState {
         match action {
             ButtonAction::SetAgent(team_id) => {
                 messages_to_send.push(ClientMessage::JoinTeam {
-                    team_id: *team_id,
+                    team_id: Some(*team_id),
                     player_type: PlayerType::Agent,
                     name: self.name.clone(),
+                    queue: false,
                 });
             }
             ButtonAction::SetDispatcher(team_id) => {
                 messages_to_send.push(ClientMessage::JoinTeam {
-                    team_id: *team_id,
+                    team_id: Some(*team_id),
+                    player_type: PlayerType::Dispatcher,
+                    name: self.name.clone(),
+                    queue: false,
+                });
+            }
+            ButtonAction::QueueDispatcher(team_id) => {
+                messages_to_send.push(ClientMessage::JoinTeam {
+                    team_id: Some(*team_id),
                     player_type: PlayerType::Dispatcher,
                     name: self.name.clone(),
+                    queue: true,
                 });
             }
+            ButtonAction::AutoAssign => {
+                messages_to_send.push(ClientMessage::JoinTeam {
+                    team_id: None,
+                    player_type: PlayerType::Agent,
+                    name: self.name.clone(),
+                    queue: false,
+                });
+            }
+            ButtonAction::ToggleReady => {
+                if let Some(player) = self.game_state.get_player_by_id(self.my_id) {
+                    messages_to_send.push(ClientMessage::SetReady(!player.ready));
+                }
+            }
         }
     }
 
@@ -196,12 +258,23 @@ impl MenuState {
 
         self.draw_player_name(canvas, assets)?;
 
+        self.draw_team_names(canvas, assets)?;
+
         self.draw_buttons(canvas, assets);
 
         if let Some(player_type) = player_type {
-            self.draw_player_status(canvas, assets, player_type, 0)
+            self.draw_player_status(canvas, assets, player_type)
                 .unwrap();
         }
+        if let Some(text) = self.queue_status() {
+            self.draw_status_line(canvas, assets, &text, 70.)?;
+        }
+        if let Some(error) = self.rejection {
+            let text = format!("Could not join: {}", error.reason());
+            self.draw_status_line(canvas, assets, &text, 100.)?;
+        }
+
+        self.draw_match_status(canvas, assets)?;
 
         canvas.present();
         Ok(())
@@ -212,21 +285,26 @@ impl MenuState {
         canvas: &mut Canvas<Window>,
         assets: &Assets,
         player_type: PlayerType,
-        team_id: u64,
     ) -> Result<(), String> {
         let (nx, ny) = constants::STATUS_TEXT_POS;
         let disp_ag_text = match player_type {
             PlayerType::Agent => "agent",
             PlayerType::Dispatcher => "dispatcher",
         };
-        let team_text = if team_id == constants::TEAM_RED_ID {
-            "RED"
-        } else {
-            "BLUE"
+        let team_text = self.game_state.team_of_player(self.my_id)
+            .and_then(|team_id| self.game_state.teams.get(&team_id))
+            .map(|team| team.name.as_str())
+            .unwrap_or("");
+        let ready_text = match self.game_state.get_player_by_id(self.my_id) {
+            Some(player) if player.ready => " (ready)",
+            _ => "",
         };
         let text = assets
             .font
-            .render(&format!("You are {} in team {}", disp_ag_text, team_text))
+            .render(&format!(
+                "You are {} in team {}{}",
+                disp_ag_text, team_text, ready_text
+            ))
             .blended((255, 255, 255))
             .expect("Could not render text");
 
@@ -237,6 +315,58 @@ impl MenuState {
         rendering::draw_texture(canvas, &text_texture, vec2(nx + 10., ny + 10.) + res_offset)
     }
 
+    /// Where we are in line for the dispatcher role, if we are waiting for
+    /// it
+    fn queue_status(&self) -> Option<String> {
+        self.game_state.sorted_teams().iter().find_map(|team| {
+            team.queue_position(self.my_id).map(|position| {
+                format!(
+                    "You are number {} in line to be dispatcher of team {}",
+                    position + 1,
+                    team.name
+                )
+            })
+        })
+    }
+
+    /// A line of text below the name, `offset` pixels down
+    fn draw_status_line(
+        &self,
+        canvas: &mut Canvas<Window>,
+        assets: &Assets,
+        text: &str,
+        offset: f32,
+    ) -> Result<(), String> {
+        let (nx, ny) = constants::STATUS_TEXT_POS;
+        let text = assets
+            .font
+            .render(text)
+            .blended((255, 255, 255))
+            .expect("Could not render text");
+
+        let texture_creator = canvas.texture_creator();
+        let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
+
+        let res_offset = rendering::calculate_resolution_offset(canvas);
+        rendering::draw_texture(canvas, &text_texture, vec2(nx + 10., ny + offset) + res_offset)
+    }
+
+    fn draw_match_status(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        assets: &Assets,
+    ) -> Result<(), String> {
+        let status = match self.game_state.match_state {
+            MatchState::Lobby => String::from("Waiting for everyone to be ready"),
+            MatchState::Countdown { time_left } => {
+                format!("Match starting in {}", time_left.ceil())
+            }
+            MatchState::InProgress { .. } => String::from("Match in progress, join a team!"),
+            MatchState::Finished { .. } => String::from("Match finished"),
+        };
+        self.draw_status_line(canvas, assets, &status, 40.)
+    }
+
     fn draw_buttons(&mut self, canvas: &mut Canvas<Window>, assets: &Assets) {
         let (width, height) = canvas.logical_size();
 
@@ -268,25 +398,59 @@ impl MenuState {
         }
     }
 
-    fn draw_background(&mut self, canvas: &mut Canvas<Window>) {
+    /// The name of each team above its column of buttons
+    fn draw_team_names(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        assets: &Assets,
+    ) -> Result<(), String> {
         let (width, height) = canvas.logical_size();
-        let line = Rect::new(
-            (width / 2 - constants::MENU_CENTER_LINE_WIDTH / 2) as i32,
-            0,
-            constants::MENU_CENTER_LINE_WIDTH,
-            height,
-        );
-
-        let left = Rect::new(0, 0, (width / 2) as u32, height);
-        let right = Rect::new((width / 2) as i32, 0, (width / 2) as u32, height);
+        let texture_creator = canvas.texture_creator();
 
-        canvas.set_draw_color(constants::MENU_RED_BACKGROUND_COLOR);
-        canvas.fill_rect(left).unwrap();
+        let teams = self.game_state.sorted_teams();
+        for (i, team) in teams.iter().enumerate() {
+            let text = assets
+                .font
+                .render(&team.name)
+                .blended(team.color)
+                .expect("Could not render text");
+            let text_texture = texture_creator.create_texture_from_surface(text).unwrap();
 
-        canvas.set_draw_color(constants::MENU_BLUE_BACKGROUND_COLOR);
-        canvas.fill_rect(right).unwrap();
+            let x = (i as f32 + 0.5) / teams.len() as f32 * width as f32;
+            let y = constants::MENU_TEAM_NAME_Y * height as f32;
+            rendering::draw_texture(canvas, &text_texture, vec2(x + 10., y))?;
+        }
+        Ok(())
+    }
 
-        canvas.set_draw_color(constants::MENU_CENTER_LINE_COLOR);
-        canvas.fill_rect(line).unwrap();
+    /// A column in the colour of each team, with lines between them
+    fn draw_background(&mut self, canvas: &mut Canvas<Window>) {
+        let (width, height) = canvas.logical_size();
+        let teams = self.game_state.sorted_teams();
+        let column_width = width / teams.len().max(1) as u32;
+
+        for (i, team) in teams.iter().enumerate() {
+            let x = (i as u32 * column_width) as i32;
+            canvas.set_draw_color(team_shade(team.color, constants::MENU_BACKGROUND_BRIGHTNESS));
+            canvas.fill_rect(Rect::new(x, 0, column_width, height)).unwrap();
+
+            if i > 0 {
+                let line = Rect::new(
+                    x - (constants::MENU_DIVIDER_WIDTH / 2) as i32,
+                    0,
+                    constants::MENU_DIVIDER_WIDTH,
+                    height,
+                );
+                canvas.set_draw_color(constants::MENU_DIVIDER_COLOR);
+                canvas.fill_rect(line).unwrap();
+            }
+        }
     }
 }
+
+/// A team colour darkened to `brightness` times the original, so that white
+/// text stays readable on top of it
+fn team_shade((r, g, b): (u8, u8, u8), brightness: f32) -> Color {
+    let shade = |component: u8| (component as f32 * brightness) as u8;
+    Color::RGB(shade(r), shade(g), shade(b))
+}
@@ -1,18 +1,22 @@
+use std::collections::HashMap;
 use std::io;
 use std::io::prelude::*;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::str::FromStr;
 use std::time::Instant;
 use std::vec;
 
 use unicode_truncate::UnicodeTruncateStr;
 
 use libplen::constants;
-use libplen::gamestate;
+use libplen::gamemode::{GameMode, Mode};
+use libplen::gamestate::{self, MatchConfig, MatchState};
 use libplen::level::{self, Level};
+use libplen::marker::Marker;
 use libplen::math::{vec2, Vec2};
 use libplen::messages::{ClientInput, ClientMessage, MessageReader, ServerMessage, SoundEffect};
-use libplen::player::{Player, PlayerType};
+use libplen::player::{JoinError, Player, PlayerType};
 
 fn send_bytes(bytes: &[u8], stream: &mut TcpStream) -> io::Result<()> {
     let mut start = 0;
@@ -36,11 +40,45 @@ fn send_bytes(bytes: &[u8], stream: &mut TcpStream) -> io::Result<()> {
 
 fn send_server_message(msg: &ServerMessage, stream: &mut TcpStream) -> io::Result<()> {
     let data = bincode::serialize(msg).expect("Failed to encode message");
+    // The length prefix would wrap around and desync the stream
+    if data.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too long to send", data.len()),
+        ));
+    }
     let length = data.len() as u16;
     send_bytes(&length.to_be_bytes(), stream)?;
     send_bytes(&data, stream)
 }
 
+/// Reads a configuration value from the environment, falling back to the
+/// default if it is missing or malformed
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            println!("Invalid value {:?} for {}, using the default", value, name);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn generate_level(team_count: usize, mode: Mode) -> Level {
+    let seed = rand::random();
+    let level = level::generate_level(seed, team_count);
+    if let Err(errors) = level.validate() {
+        for error in errors {
+            println!("Level error: {}", error);
+        }
+        panic!("Generated an invalid level from seed {}", seed);
+    }
+    if let Err(e) = mode.check_level(&level, team_count) {
+        panic!("Can't play {} on the level from seed {}: {}", mode.name(), seed, e);
+    }
+    level
+}
+
 struct Client {
     id: u64,
     message_reader: MessageReader,
@@ -52,6 +90,9 @@ struct Server {
     connections: Vec<Client>,
     state: gamestate::GameState,
     level: Level,
+    /// The level loaded from the file given in the LEVEL environment
+    /// variable. Without one, every match gets a newly generated level
+    level_file: Option<Level>,
     next_id: u64,
     last_time: Instant,
 }
@@ -64,13 +105,66 @@ impl Server {
 
         println!("Listening on 0.0.0.0:4444");
 
+        // Without a SCORE_LIMIT, every mode has a limit of its own
+        let score_limit = std::env::var("SCORE_LIMIT").ok().map(|limit| {
+            limit.parse().unwrap_or_else(|_| panic!("Invalid score limit {:?}", limit))
+        });
+        let mode_name = env_or("MODE", String::from("ctf"));
+        let mode = Mode::from_config(&mode_name, score_limit).unwrap_or_else(|e| panic!("{}", e));
+
+        let team_count = env_or("TEAMS", constants::DEFAULT_TEAM_COUNT);
+        let max_teams = constants::TEAMS.len().min(level::MAX_GENERATED_TEAMS);
+        if team_count == 0 || team_count > max_teams {
+            panic!("There can be 1 to {} teams, not {}", max_teams, team_count);
+        }
+
+        let mut state = gamestate::GameState::with_teams(team_count);
+        state.config = MatchConfig {
+            mode,
+            time_limit: env_or("TIME_LIMIT", constants::DEFAULT_TIME_LIMIT),
+        };
+        println!(
+            "{} matches between {} teams last {} seconds or until a team {}",
+            mode.name(),
+            team_count,
+            state.config.time_limit,
+            mode.goal()
+        );
+
+        let level_file = std::env::var("LEVEL").ok().map(|path| {
+            println!("Loading level from {}", path);
+            let level = Level::load(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            if level.bases().len() < team_count {
+                panic!("{} only has bases for {} teams", path, level.bases().len());
+            }
+            if let Err(e) = mode.check_level(&level, team_count) {
+                panic!("Can't play {} on {}: {}", mode.name(), path, e);
+            }
+            level
+        });
+        let level = level_file.clone().unwrap_or_else(|| generate_level(team_count, mode));
+
         Self {
             listener,
             connections: vec![],
             next_id: 0,
             last_time: Instant::now(),
-            state: gamestate::GameState::new(),
-            level: level::example_level(),
+            state,
+            level,
+            level_file,
+        }
+    }
+
+    /// Picks the level for the next match and tells every client about it
+    fn change_level(&mut self) {
+        let team_count = self.state.teams.len();
+        let mode = self.state.config.mode;
+        self.level = self.level_file.clone().unwrap_or_else(|| generate_level(team_count, mode));
+
+        let message = ServerMessage::Level(self.level.clone());
+        for client in self.connections.iter_mut() {
+            // Disconnects are dealt with when sending the game state
+            let _ = send_server_message(&message, &mut client.message_reader.stream);
         }
     }
 
@@ -83,10 +177,19 @@ impl Server {
         }
         self.last_time = Instant::now();
 
-        self.state.update(delta_time);
+        let inputs = self.connections.iter()
+            .map(|client| (client.id, client.input.clone()))
+            .collect();
+        let was_in_lobby = self.state.match_state == MatchState::Lobby;
+        let sounds = self.state.update(delta_time, &self.level, &inputs);
+        if !was_in_lobby && self.state.match_state == MatchState::Lobby {
+            // The level for the next match is picked while everyone is in
+            // the lobby, so that players can be spawned on it
+            self.change_level();
+        }
 
         self.accept_new_connections();
-        self.update_clients(delta_time);
+        self.update_clients(sounds);
     }
 
     fn accept_new_connections(&mut self) {
@@ -102,6 +205,11 @@ impl Server {
                         println!("Could not send assign id message");
                         continue;
                     }
+                    let level = ServerMessage::Level(self.level.clone());
+                    if send_server_message(&level, &mut stream).is_err() {
+                        println!("Could not send level message");
+                        continue;
+                    }
                     self.connections.push(Client {
                         id: self.next_id,
                         message_reader: MessageReader::new(stream),
@@ -121,10 +229,9 @@ impl Server {
         }
     }
 
-    fn update_clients(&mut self, delta_time: f32) {
+    fn update_clients(&mut self, sounds_to_play: Vec<(SoundEffect, Vec2)>) {
         // Send data to clients
         let mut clients_to_delete = vec![];
-        let mut sounds_to_play = vec![];
 
         macro_rules! remove_player_on_disconnect {
             ($op:expr, $id:expr) => {
@@ -136,6 +243,10 @@ impl Server {
                             clients_to_delete.push($id);
                             break;
                         }
+                        io::ErrorKind::InvalidData => {
+                            // Nothing was written, so the stream is still in sync
+                            println!("Skipped a message to {}: {}", $id, e);
+                        }
                         e => {
                             panic!("Unhandled network issue: {:?}", e)
                         }
@@ -145,6 +256,7 @@ impl Server {
         }
 
         let mut players_to_add = vec![];
+        let mut markers_to_send = vec![];
 
         for client in self.connections.iter_mut() {
             remove_player_on_disconnect!(client.message_reader.fetch_bytes(), client.id);
@@ -158,8 +270,9 @@ impl Server {
                         team_id,
                         player_type,
                         name,
+                        queue,
                     }) => {
-                        players_to_add.push((client.id, team_id, player_type, name));
+                        players_to_add.push((client.id, team_id, player_type, name, queue));
                     }
                     Ok(ClientMessage::SetName { mut name }) => {
                         if name.trim().len() != 0 {
@@ -169,30 +282,84 @@ impl Server {
                         }
                         self.state.set_player_name(client.id, name);
                     }
+                    Ok(ClientMessage::SetReady(ready)) => {
+                        self.state.set_player_ready(client.id, ready);
+                    }
+                    Ok(ClientMessage::PlaceMarker { kind, position }) => {
+                        let is_dispatcher = match self.state.get_player_by_id(client.id) {
+                            Some(player) => player.player_type == PlayerType::Dispatcher,
+                            None => false,
+                        };
+                        if let Some(team_id) = self.state.team_of_player(client.id) {
+                            if is_dispatcher {
+                                markers_to_send.push((team_id, Marker::new(kind, position)));
+                            }
+                        }
+                    }
+                    Ok(ClientMessage::SetDoor { room, direction, state }) => {
+                        self.state.set_door(client.id, room, direction, state);
+                    }
                     Err(_) => {
                         println!("Could not decode message from {}, deleting", client.id);
                         clients_to_delete.push(client.id);
                     }
                 }
             }
+        }
 
-            if let Some(player) = self.state.get_mut_player_by_id(client.id) {
-                player.update(delta_time, &client.input);
-            }
+        // Each team gets its own snapshot, which only shows the enemies that
+        // the team can see
+        let state = &self.state;
+        let level = &self.level;
+        let mut snapshots = HashMap::new();
+        for client in self.connections.iter_mut() {
+            let team_id = state.team_of_player(client.id);
+            let snapshot = snapshots
+                .entry(team_id)
+                .or_insert_with(|| ServerMessage::GameState(state.snapshot_for(team_id, level)));
 
-            let result = send_server_message(
-                &ServerMessage::GameState(self.state.clone()),
-                &mut client.message_reader.stream,
-            );
+            let result = send_server_message(snapshot, &mut client.message_reader.stream);
             remove_player_on_disconnect!(result, client.id);
         }
 
-        for (client_id, team_id, player_type, name) in players_to_add {
-            self.try_add_player_to_team(client_id, team_id, player_type, name);
+        let mut rejected_joins = vec![];
+        for (client_id, team_id, player_type, name, queue) in players_to_add {
+            if let Err(error) = self.join_team(client_id, team_id, player_type, name, queue) {
+                println!("Player {} could not join: {}", client_id, error.reason());
+                rejected_joins.push((client_id, error));
+            }
+        }
+
+        for (client_id, error) in rejected_joins {
+            for client in self.connections.iter_mut().filter(|client| client.id == client_id) {
+                let result = send_server_message(
+                    &ServerMessage::JoinRejected(error),
+                    &mut client.message_reader.stream,
+                );
+                remove_player_on_disconnect!(result, client.id);
+            }
+        }
+
+        for (team_id, marker) in markers_to_send {
+            for client in self.connections.iter_mut() {
+                if self.state.team_of_player(client.id) != Some(team_id) {
+                    continue;
+                }
+                let result = send_server_message(
+                    &ServerMessage::Marker(marker),
+                    &mut client.message_reader.stream,
+                );
+                remove_player_on_disconnect!(result, client.id);
+            }
         }
 
+        // Sounds give away where they happened, so only the teams that are
+        // close enough to hear them get them
         for (sound, pos) in &sounds_to_play {
             for client in self.connections.iter_mut() {
+                if !self.state.can_hear(self.state.team_of_player(client.id), *pos) {
+                    continue;
+                }
                 let result = send_server_message(
                     &ServerMessage::PlaySound(*sound, *pos),
                     &mut client.message_reader.stream,
@@ -209,15 +376,34 @@ impl Server {
             .retain(|client| !clients_to_delete.contains(&client.id));
     }
 
-    fn try_add_player_to_team(
+    fn join_team(
         &mut self,
         player_id: u64,
-        team_id: u64,
+        team_id: Option<u64>,
         player_type: PlayerType,
         name: String,
-    ) {
-        self.state
-            .try_add_player_to_team(player_id, team_id, player_type, name);
+        queue: bool,
+    ) -> Result<(), JoinError> {
+        let was_agent = self
+            .state
+            .get_player_by_id(player_id)
+            .map(|player| player.player_type == PlayerType::Agent)
+            .unwrap_or(false);
+        let previous_team = self.state.team_of_player(player_id);
+
+        let team_id = self
+            .state
+            .join_team(player_id, team_id, player_type, name, queue)?;
+
+        // Agents who only got in line for the dispatcher role of their own
+        // team keep playing where they are
+        let queued_in_place = was_agent
+            && player_type == PlayerType::Dispatcher
+            && previous_team == Some(team_id);
+        if !queued_in_place {
+            self.state.spawn_player(player_id, &self.level);
+        }
+        Ok(())
     }
 }
 